@@ -8,10 +8,16 @@ use regex::Regex;
 use util::write_out;
 
 const IN_PATH: &str = "src/patterns/";
+const EVENT_TYPE_IDS_PATH: &str = "src/data/event_type_ids.yml";
+/// Every event type ID which has been added, so an ID which is changed in `EVENT_TYPE_IDS_PATH` is detected even if the
+/// IDs are renumbered to stay in order. New IDs are appended to it by hand when they're added, and it's committed.
+const EVENT_TYPE_IDS_LOCK_PATH: &str = "src/data/event_type_ids.lock";
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=src/patterns/");
+    println!("cargo:rerun-if-changed={}", EVENT_TYPE_IDS_PATH);
+    println!("cargo:rerun-if-changed={}", EVENT_TYPE_IDS_LOCK_PATH);
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
     let regex_out_path = std::path::Path::new(&out_dir).join("regex_patterns.rs");
     let events_out_path = std::path::Path::new(&out_dir).join("events.rs");
@@ -32,54 +38,76 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
 
     write_out!(
         out_file_writer,
-        "use crate::parser::read_lines;\n",
+        "use crate::parser::{read_lines, VERSION_SEARCH_LINES};\n",
         "use crate::{Error, Result};\n",
         "use lazy_static::lazy_static;\n",
         "use rangemap::RangeMap;\n",
-        "use regex::Regex;\n",
+        "use regex::{Regex, RegexSet};\n",
+        "use crate::source::LogSource;\n",
         "use semver::Version;\n",
-        "use std::path::Path;\n",
+        "use std::path::PathBuf;\n",
         "use std::collections::HashMap;\n\n",
     );
 
     write_out!(
         out_file_writer,
-        "/// Loop over every line of a file and attempt to match against 'version' regex for all known formats and platforms,\n",
-        "/// returning the matching pattern, and the version, if found.\n",
-        "pub fn patterns_for_file(path: &Path) -> Result<(Patterns, Version)> {\n",
-        "    let lines = read_lines(path)?;\n",
-        "    for (_, patterns) in PATTERNS_MAP.iter() {\n",
-        "        let mut version_re_cache: Vec<Regex> = vec![];\n",
-        "        for line in &lines {\n",
-        "            for (index, platform) in patterns.platforms.iter().enumerate() {\n",
-        "                let version_re = if index < version_re_cache.len() {\n",
-        "                    &version_re_cache[index]\n",
-        "                } else {\n",
-        "                    let vr = Regex::new(platform.version).unwrap();\n",
-        "                    version_re_cache.push(vr);\n",
-        "                    &version_re_cache[index]\n",
-        "                };\n\n",
-        "                let Some(captures) = version_re.captures(line) else {\n",
-        "                    continue;\n",
-        "                };\n",
+        "/// Loop over the first `VERSION_SEARCH_LINES` lines of a file and attempt to match against 'version' regex for all\n",
+        "/// known formats and platforms, returning the version, and the name of the matching platform, if found.\n",
+        "pub fn platform_for_file(source: &LogSource) -> Result<(Version, &'static str)> {\n",
+        "    // Lines are read lazily, so we stop reading as soon as a version line is found.\n",
+        "    for line in read_lines(source)?.take(VERSION_SEARCH_LINES) {\n",
+        "        if let Some(result) = platform_for_line(&line?.text) {\n",
+        "            return result;\n",
+        "        }\n",
+        "    }\n",
+        "    Err(Error::NotLogs(PathBuf::from(source.to_string())))\n",
+        "}\n\n"
+    );
+
+    write_out!(
+        out_file_writer,
+        "/// Attempt to match a line against the 'version' regex for all known formats and platforms. If the line is a\n",
+        "/// version line, return the version and the name of the matching platform, or an error if the version is not\n",
+        "/// supported. The patterns aren't compiled, so they can be compiled once for each version and platform.\n",
+        "pub fn platform_for_line(line: &str) -> Option<Result<(Version, &'static str)>> {\n",
+        "    if !VERSION_REGEX_SET.is_match(line) {\n",
+        "        return None;\n",
+        "    }\n",
+        "    let captures = VERSION_REGEXES.iter().find_map(|version_re| version_re.captures(line))?;\n",
         "\n",
-        "                let Some(version) = captures.name(\"ver\") else {\n",
-        "                    panic!(\"YAML 'version' spec is missing 'ver' capture!\");\n",
-        "                };\n",
+        "    let Some(version) = captures.name(\"ver\") else {\n",
+        "        panic!(\"YAML 'version' spec is missing 'ver' capture!\");\n",
+        "    };\n",
         "\n",
-        "                // TODO: REMOVE TEMP FIX FOR CORE CPPTEST LOGS\n",
-        "                let version_str = if version.as_str() == \"3.2\" {\n",
-        "                    \"3.2.0\"\n",
-        "                } else {\n",
-        "                    version.as_str()\n",
-        "                };\n",
+        "    // TODO: REMOVE TEMP FIX FOR CORE CPPTEST LOGS\n",
+        "    let version_str = if version.as_str() == \"3.2\" {\n",
+        "        \"3.2.0\"\n",
+        "    } else {\n",
+        "        version.as_str()\n",
+        "    };\n",
         "\n",
-        "                let version = Version::parse(version_str).map_err(Error::Semver)?;\n",
-        "                return Ok((pattern_for_version(line, version.clone())?, version));\n",
-        "            }\n",
-        "        }\n",
-        "    }\n",
-        "    Err(Error::NotLogs(path.to_path_buf()))\n",
+        "    let version = match Version::parse(version_str) {\n",
+        "        Ok(version) => version,\n",
+        "        Err(err) => return Some(Err(Error::Semver(err))),\n",
+        "    };\n",
+        "    Some(platform_for_version(line, &version).map(|platform| (version, platform)))\n",
+        "}\n\n"
+    );
+
+    write_out!(
+        out_file_writer,
+        "/// Compile the patterns for the given version and platform name. This compiles every regex for the version, so the\n",
+        "/// patterns should be reused.\n",
+        "pub fn patterns_for_platform(version: &Version, platform: &str) -> Result<Patterns> {\n",
+        "    let pattern = PATTERNS_MAP\n",
+        "        .get(version)\n",
+        "        .ok_or_else(|| Error::UnsupportedVersion(version.clone()))?;\n",
+        "    pattern\n",
+        "        .platforms\n",
+        "        .iter()\n",
+        "        .find(|platform_patterns| platform_patterns.name == platform)\n",
+        "        .map(|platform_patterns| Patterns::from_strings(pattern, platform_patterns))\n",
+        "        .ok_or_else(|| Error::CannotParse(format!(\"No platform '{}' for CBL version {}\", platform, version)))\n",
         "}\n\n"
     );
 
@@ -87,10 +115,10 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
         out_file_writer,
         "/// Just because a version matched against a pattern, it doesn't mean the pattern is for the correct version.\n",
         "/// We need to fetch the correct pattern for the version, then get the right platform for that version.\n",
-        "fn pattern_for_version(line: &str, version: Version) -> Result<Patterns> {\n",
+        "fn platform_for_version(line: &str, version: &Version) -> Result<&'static str> {\n",
         "    let pattern = PATTERNS_MAP\n",
-        "        .get(&version)\n",
-            "        .ok_or(Error::UnsupportedVersion(version))?;\n",
+        "        .get(version)\n",
+        "        .ok_or_else(|| Error::UnsupportedVersion(version.clone()))?;\n",
         "    for platform in pattern.platforms.iter() {\n",
         "        let version_re = Regex::new(platform.version).unwrap();\n",
         "        let Some(capture) = version_re.captures(line) else {\n",
@@ -101,7 +129,7 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
         "            \"YAML 'version' spec is missing 'ver' capture!\"\n",
         "        );\n",
         "\n",
-        "        return Ok(Patterns::from_strings(pattern, platform));\n",
+        "        return Ok(platform.name);\n",
         "    }\n",
         "    Err(Error::UnsupportedPlatform(line.to_string()))\n",
         "}\n\n",
@@ -125,6 +153,7 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
         out_file_writer,
         "#[derive(Debug, Clone)]\n",
         "struct PlatformPatternStrings {\n",
+        "    pub name: &'static str,\n",
         "    pub version: &'static str,\n",
         "    pub timestamp: &'static str,\n",
         "    pub full_timestamp: bool,\n",
@@ -163,6 +192,8 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
         out_file_writer,
         "#[derive(Debug, Clone)]\n",
         "pub struct PlatformPatterns {\n",
+        "    /// The name of the platform, as given in the YAML\n",
+        "    pub name: &'static str,\n",
         "    pub version: Regex,\n",
         "    pub timestamp: Regex,\n",
         "    pub full_timestamp: bool,\n",
@@ -188,6 +219,7 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
         "impl From<&PlatformPatternStrings> for PlatformPatterns {\n",
         "    fn from(patterns: &PlatformPatternStrings) -> Self {\n",
         "        PlatformPatterns {\n",
+        "            name: patterns.name,\n",
         "            version: Regex::new(patterns.version).unwrap(),\n",
         "            timestamp: Regex::new(patterns.timestamp).unwrap(),\n",
         "            full_timestamp: patterns.full_timestamp,\n",
@@ -222,10 +254,17 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
             write_out!(
                 out_file_writer,
                 "    static ref PLATFORM_{}_{}: PlatformPatternStrings = PlatformPatternStrings {{\n",
+                "        name: r#\"{}\"#,\n",
                 "        version: r#\"{}\"#,\n",
                 "        timestamp: r#\"{}\"#,\n",
                 "        timestamp_formats: vec![\n",
-                args!(pattern_index, platform_index, platform.version, platform.timestamp)
+                args!(
+                    pattern_index,
+                    platform_index,
+                    platform.name,
+                    platform.version,
+                    platform.timestamp
+                )
             );
 
             for timestamp_format in &platform.timestamp_formats {
@@ -329,6 +368,26 @@ fn create_regex_patterns(out_path: &Path, formats: &BTreeMap<Compatibility, Patt
 
     write_out!(out_file_writer, "}\n\n");
 
+    write_out!(
+        out_file_writer,
+        "lazy_static! {\n",
+        "    /// The 'version' regex of every platform of every format.\n",
+        "    static ref VERSION_REGEXES: Vec<Regex> = PATTERNS_MAP\n",
+        "        .iter()\n",
+        "        .flat_map(|(_, patterns)| patterns.platforms.iter())\n",
+        "        .map(|platform| Regex::new(platform.version).unwrap())\n",
+        "        .collect();\n",
+        "    /// Quickly check whether a line matches any of `VERSION_REGEXES`.\n",
+        "    static ref VERSION_REGEX_SET: RegexSet = RegexSet::new(\n",
+        "        PATTERNS_MAP\n",
+        "            .iter()\n",
+        "            .flat_map(|(_, patterns)| patterns.platforms.iter())\n",
+        "            .map(|platform| platform.version)\n",
+        "    )\n",
+        "    .unwrap();\n",
+        "}\n\n",
+    );
+
     out_file_writer
             .write_all(
                 concat!(
@@ -423,20 +482,49 @@ fn create_events(out_path: &Path, formats: &BTreeMap<Compatibility, Patterns>) {
         "pub enum EventType {\n"
     );
 
-    let all_event_keys = formats
-        .iter()
-        .flat_map(|(_, patterns)| patterns.events.keys())
-        .collect::<BTreeSet<_>>();
+    // The discriminants are the permanent IDs stored in the database, so they mustn't depend on which events exist.
+    let event_type_ids = event_type_ids(formats);
 
-    for key in all_event_keys {
-        let key = snake_to_pascal_case(key);
-        write_out!(out_file_writer, "    {},\n", args!(&key));
+    for (key, id) in &event_type_ids {
+        if key == "unknown" {
+            write_out!(
+                out_file_writer,
+                "    /// A CBL line which did not match any known event.\n"
+            );
+        }
+        write_out!(
+            out_file_writer,
+            "    {} = {},\n",
+            args!(snake_to_pascal_case(key), id)
+        );
     }
 
     write_out!(out_file_writer, "}\n\n");
 
     write_out!(out_file_writer, "impl_display_debug!(EventType);\n\n");
 
+    write_out!(
+        out_file_writer,
+        "impl EventType {\n",
+        "    /// The event type with the given ID, or `None` if the ID isn't known to this build.\n",
+        "    pub fn from_id(id: u32) -> Option<Self> {\n",
+        "        match id {\n",
+    );
+    for (key, id) in &event_type_ids {
+        write_out!(
+            out_file_writer,
+            "            {} => Some(Self::{}),\n",
+            args!(id, snake_to_pascal_case(key))
+        );
+    }
+    write_out!(
+        out_file_writer,
+        "            _ => None,\n",
+        "        }\n",
+        "    }\n",
+        "}\n\n"
+    );
+
     write_out!(
         out_file_writer,
         "trait EventBuilder {\n",
@@ -471,19 +559,6 @@ fn create_events(out_path: &Path, formats: &BTreeMap<Compatibility, Patterns>) {
             }
         }
 
-        if let Some(tests_only_ignored) = &patterns.tests_only_ignored {
-            for i in 0..tests_only_ignored.len() {
-                write_out!(
-                    out_file_writer,
-                    "        #[cfg(debug_assertions)]\n",
-                    "        if patterns.tests_only_ignored[{}].is_match(line) {{\n",
-                    "            return Err(Error::IgnoredEvent);\n",
-                    "        }}\n",
-                    args!(i)
-                );
-            }
-        }
-
         for (
             event_key,
             Event {
@@ -685,6 +760,97 @@ fn parse_yaml() -> BTreeMap<Compatibility, Patterns> {
     formats
 }
 
+/// Read the event type IDs in the file at `path`, in order.
+fn read_event_type_ids(path: &str) -> Vec<(String, u32)> {
+    let file_contents = std::fs::read_to_string(path).unwrap();
+    // A `Mapping` keeps the order of the file, so an ID which was changed can be detected.
+    let mapping: Option<serde_yaml::Mapping> = serde_yaml::from_str(&file_contents).unwrap();
+    mapping
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, id)| {
+            let key = key.as_str().expect("Event type keys must be strings");
+            let id = id.as_u64().expect("Event type IDs must be integers");
+            (key.to_string(), id as u32)
+        })
+        .collect()
+}
+
+/// Check that every ID in `EVENT_TYPE_IDS_LOCK_PATH` is still in `ids` with the same key, so IDs are only ever
+/// appended, and that every ID in `ids` has been locked. Panics if an ID was changed or removed, or isn't locked yet.
+/// The lock is only read, as the build mustn't change the source tree.
+fn check_event_type_ids_lock(ids: &[(String, u32)]) {
+    let locked = read_event_type_ids(EVENT_TYPE_IDS_LOCK_PATH);
+    for (index, (key, id)) in locked.iter().enumerate() {
+        assert!(
+            ids.get(index) == Some(&(key.clone(), *id)),
+            "Event type '{}' had ID {}, which has been changed or removed in {}. IDs must never change or be reused, \
+             as databases written by earlier builds store them. New event types are added at the end of {}. If the ID \
+             was never committed, remove it from {} as well.",
+            key,
+            id,
+            EVENT_TYPE_IDS_PATH,
+            EVENT_TYPE_IDS_PATH,
+            EVENT_TYPE_IDS_LOCK_PATH,
+        );
+    }
+
+    let new_ids = &ids[locked.len()..];
+    assert!(
+        new_ids.is_empty(),
+        "Event type IDs {:?} aren't locked yet. Append these lines to {} and commit it with {}:\n{}",
+        new_ids.iter().map(|(_, id)| id).collect::<Vec<_>>(),
+        EVENT_TYPE_IDS_LOCK_PATH,
+        EVENT_TYPE_IDS_PATH,
+        new_ids
+            .iter()
+            .map(|(key, id)| format!("{}: {}\n", key, id))
+            .collect::<String>(),
+    );
+}
+
+/// Read the permanent ID of each event type from `EVENT_TYPE_IDS_PATH`, in order. Panics if the IDs have been changed,
+/// or if an event in `formats` has no ID.
+fn event_type_ids(formats: &BTreeMap<Compatibility, Patterns>) -> Vec<(String, u32)> {
+    let ids = read_event_type_ids(EVENT_TYPE_IDS_PATH);
+
+    for (index, (key, id)) in ids.iter().enumerate() {
+        assert!(
+            *id as usize == index,
+            "Event type '{}' has ID {} in {}, but should have ID {}. IDs must never change, and new event types are \
+             added at the end with the next ID.",
+            key,
+            id,
+            EVENT_TYPE_IDS_PATH,
+            index,
+        );
+    }
+    assert!(
+        ids.iter().any(|(key, _)| key == "unknown"),
+        "{} has no ID for 'unknown'",
+        EVENT_TYPE_IDS_PATH
+    );
+
+    check_event_type_ids_lock(&ids);
+
+    let all_event_keys = formats
+        .values()
+        .flat_map(|patterns| patterns.events.keys())
+        .collect::<BTreeSet<_>>();
+    for event_key in all_event_keys {
+        assert!(
+            ids.iter().any(|(key, _)| key == event_key),
+            "Event type '{}' has no ID. Add it to the end of {} as '{}: {}'.",
+            event_key,
+            EVENT_TYPE_IDS_PATH,
+            event_key,
+            ids.len(),
+        );
+    }
+
+    ids
+}
+
 #[derive(serde::Deserialize)]
 struct Patterns {
     platforms: Vec<PlatformPatterns>,
@@ -695,6 +861,7 @@ struct Patterns {
 
 #[derive(serde::Deserialize)]
 struct PlatformPatterns {
+    name: String,
     version: String,
     timestamp: String,
     full_timestamp: bool,
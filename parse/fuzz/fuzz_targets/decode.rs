@@ -0,0 +1,12 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+// Decoding any input must return errors rather than panicking.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(decoder) = lumberjack_parse::decoder::Decoder::new(Cursor::new(data)) {
+        for _ in decoder {}
+    }
+});
@@ -0,0 +1,552 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+use miniz_oxide::{
+    inflate::stream::{inflate, InflateState},
+    DataFormat, MZError, MZFlush, MZStatus,
+};
+
+use crate::{source::ReadSeek, Error, Result};
+
+/// The number of decompressed bytes between the checkpoints of an `Inflater`, so reading from any position
+/// decompresses at most this much data which isn't used.
+const CHECKPOINT_BYTES: u64 = 4 * 1024 * 1024;
+/// The number of compressed bytes which an `Inflater` reads at once.
+const INPUT_BYTES: usize = 32 * 1024;
+
+// Flags of the optional fields in a gzip header.
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+const GZIP_FHCRC: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// The files within an archive and where their data is, found by reading through the archive once. Files are then read
+/// straight from the archive, without extracting them.
+pub(crate) struct ArchiveIndex {
+    kind: ArchiveKind,
+    /// The files (not directories) in the archive, by their path within it.
+    entries: BTreeMap<String, Entry>,
+    /// The checkpoints of the tar within a .tar.gz, which are shared by the readers of every file within it.
+    checkpoints: Checkpoints,
+}
+
+struct Entry {
+    /// The position of the file's data in the archive, or in the decompressed tar of a .tar.gz.
+    offset: u64,
+    /// The size of the file.
+    size: u64,
+    /// The size of the compressed data and the checkpoints for reading it, for a compressed file within a zip.
+    compressed: Option<(u64, Checkpoints)>,
+    /// The start of the file, up to the length given to `ArchiveIndex::new`.
+    head: Vec<u8>,
+}
+
+impl fmt::Debug for ArchiveIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveIndex")
+            .field("kind", &self.kind)
+            .field("entries", &self.entries.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArchiveIndex {
+    /// Read through the archive read from `reader`, keeping the first `head_len` bytes of each file within it.
+    pub fn new(reader: Box<dyn ReadSeek>, kind: ArchiveKind, head_len: u64) -> Result<Self> {
+        let checkpoints = Checkpoints::default();
+        let entries = match kind {
+            ArchiveKind::Zip => index_zip(reader, head_len)?,
+            ArchiveKind::Tar => index_tar(reader, head_len)?,
+            ArchiveKind::TarGz => index_tar(
+                Inflater::new(reader, Format::Gzip, checkpoints.clone()),
+                head_len,
+            )?,
+        };
+        Ok(Self {
+            kind,
+            entries,
+            checkpoints,
+        })
+    }
+
+    /// The paths of the files within the archive, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// The start of the file at `name`, which was kept when the archive was read.
+    pub fn head(&self, name: &str) -> Result<&[u8]> {
+        Ok(&self.entry(name)?.head)
+    }
+
+    /// Open the file at `name` within the archive read from `reader`. The file is decompressed as it's read.
+    pub fn open(&self, reader: Box<dyn ReadSeek>, name: &str) -> Result<Box<dyn ReadSeek>> {
+        let entry = self.entry(name)?;
+        let (data, offset): (Box<dyn ReadSeek>, u64) = match (self.kind, &entry.compressed) {
+            (ArchiveKind::TarGz, _) => (
+                Box::new(Inflater::new(
+                    reader,
+                    Format::Gzip,
+                    self.checkpoints.clone(),
+                )),
+                entry.offset,
+            ),
+            (_, Some((compressed_size, checkpoints))) => (
+                Box::new(Inflater::new(
+                    Box::new(Window::new(reader, entry.offset, *compressed_size)),
+                    Format::Raw,
+                    checkpoints.clone(),
+                )),
+                0,
+            ),
+            (_, None) => (reader, entry.offset),
+        };
+        Ok(Box::new(Window::new(data, offset, entry.size)))
+    }
+
+    fn entry(&self, name: &str) -> Result<&Entry> {
+        self.entries
+            .get(name)
+            .ok_or_else(|| Error::CannotParse(format!("No file '{}' in the archive", name)))
+    }
+}
+
+fn index_zip(reader: Box<dyn ReadSeek>, head_len: u64) -> Result<BTreeMap<String, Entry>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut entries = BTreeMap::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if !file.is_file() {
+            continue;
+        }
+        let name = file.name().to_string();
+        let compressed = match file.compression() {
+            zip::CompressionMethod::Stored => None,
+            zip::CompressionMethod::Deflated => {
+                Some((file.compressed_size(), Checkpoints::default()))
+            }
+            method => {
+                return Err(Error::CannotParse(format!(
+                    "'{}' is compressed with {:?}, which isn't supported",
+                    name, method
+                )))
+            }
+        };
+        let mut head = vec![];
+        (&mut file).take(head_len).read_to_end(&mut head)?;
+        entries.entry(name).or_insert(Entry {
+            offset: file.data_start(),
+            size: file.size(),
+            compressed,
+            head,
+        });
+    }
+    Ok(entries)
+}
+
+fn index_tar(reader: impl Read + Seek, head_len: u64) -> Result<BTreeMap<String, Entry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = BTreeMap::new();
+    // The data of each file is seeked past, so a .tar.gz is decompressed once and a .tar isn't read at all.
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let (offset, size) = (entry.raw_file_position(), entry.size());
+        let mut head = vec![];
+        (&mut entry).take(head_len).read_to_end(&mut head)?;
+        entries.entry(name).or_insert(Entry {
+            offset,
+            size,
+            compressed: None,
+            head,
+        });
+    }
+    Ok(entries)
+}
+
+/// A part of a reader, i.e. the data of a file within an archive, which is read as though it were the whole reader.
+struct Window {
+    reader: Box<dyn ReadSeek>,
+    start: u64,
+    len: u64,
+    pos: u64,
+    /// Whether `reader` is at `start + pos`, so it doesn't need to be seeked before it's read.
+    in_place: bool,
+}
+
+impl Window {
+    fn new(reader: Box<dyn ReadSeek>, start: u64, len: u64) -> Self {
+        Self {
+            reader,
+            start,
+            len,
+            pos: 0,
+            in_place: false,
+        }
+    }
+}
+
+impl Read for Window {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.in_place {
+            self.reader.seek(SeekFrom::Start(self.start + self.pos))?;
+            self.in_place = true;
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let len = self.reader.read(&mut buf[..len])?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Window {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Can't seek before the start of the file",
+            )
+        })?;
+        if pos != self.pos {
+            self.pos = pos;
+            self.in_place = false;
+        }
+        Ok(pos)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Deflate data without a header, i.e. a file within a zip.
+    Raw,
+    /// A gzip file, of which only the first member is read, like `flate2::read::GzDecoder`.
+    Gzip,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    Header,
+    Data,
+    End,
+}
+
+/// The state of an `Inflater` at a position in the decompressed data, which any `Inflater` of the same data can
+/// continue from.
+struct Checkpoint {
+    decompressed_pos: u64,
+    compressed_pos: u64,
+    state: Box<InflateState>,
+}
+
+/// The checkpoints of compressed data, in order, which are added to by whichever `Inflater` first reads past them.
+type Checkpoints = Arc<Mutex<Vec<Checkpoint>>>;
+
+/// A reader of deflate-compressed data, which is decompressed as it's read. Seeking forwards decompresses the data which
+/// is seeked past, while seeking backwards continues from the nearest checkpoint before the new position. The state of
+/// the decompressor is saved as a checkpoint every `CHECKPOINT_BYTES`, so seeking within the data doesn't decompress it
+/// from the start again.
+struct Inflater {
+    /// The compressed data, which is at its start when the `Inflater` is created.
+    reader: Box<dyn ReadSeek>,
+    format: Format,
+    phase: Phase,
+    state: Box<InflateState>,
+    /// Compressed data which has been read from `reader`, and has been decompressed up to `input_start`.
+    input: Vec<u8>,
+    input_start: usize,
+    /// The position in `reader` of the next compressed byte to be decompressed.
+    compressed_pos: u64,
+    /// The position in the decompressed data of the next byte to be decompressed.
+    decompressed_pos: u64,
+    /// The position which will be read from next. Seeking only moves this, and the data is decompressed up to it when
+    /// it's read.
+    pos: u64,
+    checkpoints: Checkpoints,
+}
+
+impl Inflater {
+    fn new(reader: Box<dyn ReadSeek>, format: Format, checkpoints: Checkpoints) -> Self {
+        Self {
+            reader,
+            format,
+            phase: Self::first_phase(format),
+            state: InflateState::new_boxed(DataFormat::Raw),
+            input: vec![],
+            input_start: 0,
+            compressed_pos: 0,
+            decompressed_pos: 0,
+            pos: 0,
+            checkpoints,
+        }
+    }
+
+    fn first_phase(format: Format) -> Phase {
+        match format {
+            Format::Raw => Phase::Data,
+            Format::Gzip => Phase::Header,
+        }
+    }
+
+    /// Continue decompressing from `compressed_pos` in `reader`, which is `decompressed_pos` in the decompressed data.
+    fn restart(
+        &mut self,
+        phase: Phase,
+        state: Box<InflateState>,
+        compressed_pos: u64,
+        decompressed_pos: u64,
+    ) -> std::io::Result<()> {
+        self.reader.seek(SeekFrom::Start(compressed_pos))?;
+        self.phase = phase;
+        self.state = state;
+        self.input.clear();
+        self.input_start = 0;
+        self.compressed_pos = compressed_pos;
+        self.decompressed_pos = decompressed_pos;
+        Ok(())
+    }
+
+    /// Decompress the data up to `pos`, continuing from the nearest checkpoint before it if that's nearer than the data
+    /// which has been decompressed already.
+    fn skip_to(&mut self, pos: u64) -> std::io::Result<()> {
+        let restart = {
+            let checkpoints = self.checkpoints.lock().unwrap();
+            let nearest = checkpoints
+                .partition_point(|checkpoint| checkpoint.decompressed_pos <= pos)
+                .checked_sub(1)
+                .map(|index| &checkpoints[index]);
+            match nearest {
+                Some(checkpoint)
+                    if pos < self.decompressed_pos
+                        || self.decompressed_pos < checkpoint.decompressed_pos =>
+                {
+                    Some((
+                        Phase::Data,
+                        checkpoint.state.clone(),
+                        checkpoint.compressed_pos,
+                        checkpoint.decompressed_pos,
+                    ))
+                }
+                None if pos < self.decompressed_pos => Some((
+                    Self::first_phase(self.format),
+                    InflateState::new_boxed(DataFormat::Raw),
+                    0,
+                    0,
+                )),
+                _ => None,
+            }
+        };
+        if let Some((phase, state, compressed_pos, decompressed_pos)) = restart {
+            self.restart(phase, state, compressed_pos, decompressed_pos)?;
+        }
+        let mut skipped = vec![];
+        while self.decompressed_pos < pos {
+            if skipped.is_empty() {
+                skipped = vec![0; INPUT_BYTES];
+            }
+            let len = usize::try_from(pos - self.decompressed_pos)
+                .unwrap_or(usize::MAX)
+                .min(skipped.len());
+            if self.inflate(&mut skipped[..len])? == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompress the next data into `buf`, returning its length, which is 0 at the end of the data.
+    fn inflate(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut stalled = false;
+        loop {
+            match self.phase {
+                Phase::Header => {
+                    self.skip_gzip_header()?;
+                    self.phase = Phase::Data;
+                }
+                Phase::Data => {
+                    // The end of the compressed data may be reached before the decompressor has reported the end of
+                    // the stream, which it does when it's called again, so it's only an error if it can't continue.
+                    let ended =
+                        (stalled || self.input_start == self.input.len()) && self.fill()? == 0;
+                    if ended && stalled {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "The compressed data ended early",
+                        ));
+                    }
+                    let result = inflate(
+                        &mut self.state,
+                        &self.input[self.input_start..],
+                        buf,
+                        MZFlush::None,
+                    );
+                    self.input_start += result.bytes_consumed;
+                    self.compressed_pos += result.bytes_consumed as u64;
+                    self.decompressed_pos += result.bytes_written as u64;
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => self.phase = Phase::End,
+                        Ok(_) => self.checkpoint(),
+                        // More compressed data is needed.
+                        Err(MZError::Buf) => {}
+                        Err(_) => {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                "The compressed data is corrupt",
+                            ))
+                        }
+                    }
+                    if result.bytes_written > 0 {
+                        return Ok(result.bytes_written);
+                    }
+                    stalled = result.bytes_consumed == 0;
+                }
+                Phase::End => return Ok(0),
+            }
+        }
+    }
+
+    /// Save the state of the decompressor if it's `CHECKPOINT_BYTES` past the last checkpoint.
+    fn checkpoint(&self) {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        let last_pos = checkpoints
+            .last()
+            .map_or(0, |checkpoint| checkpoint.decompressed_pos);
+        if self.decompressed_pos >= last_pos + CHECKPOINT_BYTES {
+            checkpoints.push(Checkpoint {
+                decompressed_pos: self.decompressed_pos,
+                compressed_pos: self.compressed_pos,
+                state: self.state.clone(),
+            });
+        }
+    }
+
+    /// Read more compressed data from `reader` after the data which hasn't been decompressed yet, returning its length.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        self.input.drain(..self.input_start);
+        self.input_start = 0;
+        let start = self.input.len();
+        self.input.resize(start + INPUT_BYTES, 0);
+        let len = self.reader.read(&mut self.input[start..])?;
+        self.input.truncate(start + len);
+        Ok(len)
+    }
+
+    fn next_byte(&mut self) -> std::io::Result<u8> {
+        if self.input_start == self.input.len() && self.fill()? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The gzip header ended early",
+            ));
+        }
+        let byte = self.input[self.input_start];
+        self.input_start += 1;
+        self.compressed_pos += 1;
+        Ok(byte)
+    }
+
+    /// Skip the header at the start of a gzip file, which has optional fields after its first 10 bytes.
+    fn skip_gzip_header(&mut self) -> std::io::Result<()> {
+        let mut header = [0_u8; 10];
+        for byte in &mut header {
+            *byte = self.next_byte()?;
+        }
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a gzip file",
+            ));
+        }
+        let flags = header[3];
+        if flags & GZIP_FEXTRA != 0 {
+            let len = u16::from_le_bytes([self.next_byte()?, self.next_byte()?]);
+            for _ in 0..len {
+                self.next_byte()?;
+            }
+        }
+        for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+            if flags & flag != 0 {
+                while self.next_byte()? != 0 {}
+            }
+        }
+        if flags & GZIP_FHCRC != 0 {
+            self.next_byte()?;
+            self.next_byte()?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Inflater {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.skip_to(self.pos)?;
+        let len = self.inflate(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Inflater {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            // The length of the decompressed data isn't known until it's all been decompressed, so this is left to the
+            // `Window` of the file which is being read.
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Compressed data can't be seeked from the end",
+                ))
+            }
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Can't seek before the start of the file",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
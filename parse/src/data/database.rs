@@ -1,9 +1,28 @@
+use std::collections::HashMap;
 use std::path::Path;
 
+use rusqlite::{params, OptionalExtension, Transaction};
+
+use crate::data::{EventType, Insertable};
 use crate::{Error, Result};
 
-const MIGRATIONS: &str = include_str!("./schema.sql");
+/// A change to the schema, along with any change to the data it needs.
+type Migration = fn(&mut Transaction) -> Result<()>;
+
+/// Every change to the schema, in order. The schema version of a database is the number of migrations which have been
+/// applied to it, so migrations must only be added to the end, and never changed once released.
+const MIGRATIONS: &[Migration] = &[
+    |tx| Ok(tx.execute_batch(include_str!("./migrations/0001_initial.sql"))?),
+    |tx| Ok(tx.execute_batch(include_str!("./migrations/0002_sessions_and_sources.sql"))?),
+    permanent_event_type_ids,
+];
+
+/// The version of the schema written by this build. See the `schema_version` table.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
 
+/// Open the database at `path`, creating it if it doesn't exist, or replacing it if `reset`.
+/// An existing database written by an earlier version is upgraded to the current schema in place. Fails with
+/// `Error::SchemaTooNew` if it was written by a later version.
 pub fn open_db(path: &Path, reset: bool) -> Result<rusqlite::Connection> {
     if reset && path.exists() {
         std::fs::remove_file(path)
@@ -18,7 +37,7 @@ pub fn open_db(path: &Path, reset: bool) -> Result<rusqlite::Connection> {
 
     let flags =
         rusqlite::OpenFlags::SQLITE_OPEN_CREATE | rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE;
-    let conn = rusqlite::Connection::open_with_flags(path, flags)?;
+    let mut conn = rusqlite::Connection::open_with_flags(path, flags)?;
 
     if reset {
         // Optimization for fast bulk inserts
@@ -31,11 +50,355 @@ pub fn open_db(path: &Path, reset: bool) -> Result<rusqlite::Connection> {
                 PRAGMA temp_store=MEMORY;
             ",
         )?;
-        // Create the schema
-        conn.execute_batch(MIGRATIONS)?;
     }
 
+    // A new database is created by applying every migration.
+    migrate(&mut conn)?;
+
     log::debug!("Database opened at {:?}", path);
 
     Ok(conn)
 }
+
+/// The schema version of the database of `conn`, without recording it.
+fn schema_version(conn: &rusqlite::Connection) -> Result<u32> {
+    let has_table = |name: &str| -> Result<bool> {
+        Ok(conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = $1)",
+            [name],
+            |row| row.get(0),
+        )?)
+    };
+    if has_table("schema_version")? {
+        let version: Option<u32> = conn
+            .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+            .optional()?;
+        if let Some(version) = version {
+            return Ok(version);
+        }
+    }
+    // Databases written before the schema was versioned have the initial schema.
+    Ok(u32::from(has_table("lines")?))
+}
+
+/// Apply the migrations which the database is missing, and record its new schema version.
+fn migrate(conn: &mut rusqlite::Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew(version, SCHEMA_VERSION));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if version > 0 {
+        log::info!(
+            "Upgrading database from schema version {} to {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+
+    // Foreign keys stop tables from being rebuilt, and can only be turned off outside of a transaction.
+    let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = apply_migrations(conn, version);
+    conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+    result
+}
+
+/// Apply the migrations after `version` in a single transaction, so a failed upgrade leaves the database unchanged.
+fn apply_migrations(conn: &mut rusqlite::Connection, version: u32) -> Result<()> {
+    let mut tx = conn.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        log::debug!("Applying migration {}", index + 1);
+        migration(&mut tx)?;
+    }
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS schema_version(version INTEGER NOT NULL);
+        DELETE FROM schema_version;",
+    )?;
+    tx.execute(
+        "INSERT INTO schema_version (version) VALUES ($1)",
+        [SCHEMA_VERSION],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Translate the event types of a database written before event type IDs were permanent, when each build numbered
+/// them differently, to the IDs of this build by name. Names which this build doesn't know become
+/// `EventType::Unknown`.
+fn permanent_event_type_ids(tx: &mut Transaction) -> Result<()> {
+    // A new database has no event types until they're inserted with the permanent IDs.
+    let old_event_types = tx
+        .prepare("SELECT id, name FROM event_types")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(u32, String)>>>()?;
+    if old_event_types.is_empty() {
+        return Ok(());
+    }
+
+    let ids: HashMap<String, u32> = enum_iterator::all::<EventType>()
+        .map(|event_type| (event_type.to_string(), event_type as u32))
+        .collect();
+
+    tx.execute_batch(
+        "CREATE TEMP TABLE event_type_ids(old_id INTEGER PRIMARY KEY NOT NULL, id INTEGER NOT NULL)",
+    )?;
+    for (old_id, name) in old_event_types {
+        let id = ids.get(&name).copied().unwrap_or_else(|| {
+            log::warn!(
+                "Event type '{}' is unknown to this version, its lines are read as {}",
+                name,
+                EventType::Unknown
+            );
+            EventType::Unknown as u32
+        });
+        tx.execute(
+            "INSERT INTO temp.event_type_ids (old_id, id) VALUES ($1, $2)",
+            params![old_id, id],
+        )?;
+    }
+    tx.execute_batch(
+        "
+        UPDATE lines SET event_type = (
+            SELECT id FROM temp.event_type_ids WHERE old_id = lines.event_type
+        );
+        DELETE FROM event_types;
+        DROP TABLE temp.event_type_ids;",
+    )?;
+    enum_iterator::all::<EventType>().db_insert(tx)?;
+    Ok(())
+}
+
+/// Number every line across all files in timestamp order, so lines from different files are interleaved by `seq`.
+/// Lines with the same timestamp are ordered by file, then by line within the file.
+/// Only lines from the first line without a `seq` onwards are numbered, so lines which are added after the database
+/// was sequenced (i.e. when following logs) are numbered without renumbering the lines before them. Lines after them
+/// are numbered again, so the `seq` of a line changes if lines with an earlier timestamp are added later.
+pub(crate) fn sequence_lines(conn: &rusqlite::Connection) -> Result<()> {
+    let first: Option<(rusqlite::types::Value, u32, u32)> = conn
+        .query_row(
+            "
+            SELECT timestamp, file_id, line_num FROM lines
+            WHERE seq IS NULL
+            ORDER BY timestamp, file_id, line_num
+            LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let Some((timestamp, file_id, line_num)) = first else {
+        return Ok(());
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    // The lines which are already sequenced are numbered from 1 in order, so the number of lines before the first
+    // line is the `seq` of the last of them. This is found by searching back from the last line, so only the lines
+    // after the first line are read, rather than the whole table.
+    let preceding: u64 = tx
+        .query_row(
+            "
+            SELECT seq FROM lines
+            WHERE seq IS NOT NULL AND (timestamp, file_id, line_num) < ($1, $2, $3)
+            ORDER BY seq DESC
+            LIMIT 1",
+            params![timestamp, file_id, line_num],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    // Cleared first, so the unique index on `seq` isn't violated while the lines are renumbered.
+    tx.execute(
+        "UPDATE lines SET seq = NULL WHERE seq > $1",
+        params![preceding],
+    )?;
+    tx.execute(
+        "
+        UPDATE lines SET seq = $1 + sequenced.seq
+        FROM (
+            SELECT file_id, line_num, ROW_NUMBER() OVER (ORDER BY timestamp, file_id, line_num) AS seq
+            FROM lines
+            WHERE seq IS NULL
+        ) AS sequenced
+        WHERE lines.file_id = sequenced.file_id AND lines.line_num = sequenced.line_num",
+        params![preceding],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Add the event types which the database doesn't have yet, i.e. those added since a build with the same schema
+/// version wrote it, so lines of those types can be joined with their names.
+pub(crate) fn insert_missing_event_types(conn: &mut rusqlite::Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    for event_type in enum_iterator::all::<EventType>() {
+        tx.execute(
+            "INSERT OR IGNORE INTO event_types (id, name) VALUES ($1, $2)",
+            params![event_type as u32, event_type.to_string()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Delete the files with the given IDs, along with everything parsed from them.
+/// The lines after the first deleted line are left without a `seq`, so `sequence_lines` numbers the remaining lines
+/// without gaps.
+pub(crate) fn delete_files(conn: &mut rusqlite::Connection, ids: &[u32]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for id in ids {
+        tx.execute(
+            "UPDATE lines SET seq = NULL WHERE seq >= (SELECT MIN(seq) FROM lines WHERE file_id = $1)",
+            [id],
+        )?;
+        for table in ["lines", "sessions", "metadata", "parse_errors"] {
+            tx.execute(&format!("DELETE FROM {} WHERE file_id = $1", table), [id])?;
+        }
+        tx.execute("DELETE FROM files WHERE id = $1", [id])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Copy everything in the database at `path` into the database of `conn`, tagging its files with `database` and
+/// `device`. File and session IDs are offset past those already in `conn`, and event types are matched by name, as
+/// their IDs depend on the build which wrote the database. Event types which this build doesn't know are stored as
+/// `EventType::Unknown`. The copied lines are left without a `seq`, for `sequence_lines` to number.
+/// Files which were already merged from another database keep their original tags.
+pub(crate) fn merge_db(
+    conn: &mut rusqlite::Connection,
+    path: &Path,
+    database: &str,
+    device: &str,
+) -> Result<()> {
+    // Attaching a path which doesn't exist would create an empty database.
+    if !path.is_file() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No database at {:?}", path),
+        )));
+    }
+    // Inputs written by earlier versions are upgraded first, so their rows have every column. The upgrade is applied
+    // to a copy, as merging mustn't change its inputs.
+    let version = schema_version(&rusqlite::Connection::open_with_flags(
+        path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew(version, SCHEMA_VERSION));
+    }
+    let upgraded = if version < SCHEMA_VERSION {
+        log::debug!("Upgrading a copy of {:?} to merge", path);
+        let copy = tempfile::NamedTempFile::new()?;
+        std::fs::copy(path, copy.path())?;
+        drop(open_db(copy.path(), false)?);
+        Some(copy)
+    } else {
+        None
+    };
+    let attach_path = upgraded
+        .as_ref()
+        .map_or(path, tempfile::NamedTempFile::path);
+
+    conn.execute(
+        "ATTACH DATABASE $1 AS input",
+        [attach_path.to_string_lossy()],
+    )?;
+    let result = copy_attached(conn, path, database, device);
+    conn.execute("DETACH DATABASE input", [])?;
+    result
+}
+
+/// Copy the rows of the database attached as `input` into the main database, for `merge_db`.
+fn copy_attached(
+    conn: &mut rusqlite::Connection,
+    path: &Path,
+    database: &str,
+    device: &str,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    let file_offset: u32 = tx.query_row(
+        "SELECT COALESCE(MAX(id) + 1, 0) FROM main.files",
+        [],
+        |row| row.get(0),
+    )?;
+    let session_offset: u32 = tx.query_row(
+        "SELECT COALESCE(MAX(id) + 1, 0) FROM main.sessions",
+        [],
+        |row| row.get(0),
+    )?;
+
+    tx.execute_batch(
+        "
+        CREATE TEMP TABLE event_type_ids AS
+        SELECT input.event_types.id AS input_id, main.event_types.id AS id, input.event_types.name AS name
+        FROM input.event_types
+        LEFT JOIN main.event_types ON main.event_types.name = input.event_types.name",
+    )?;
+    let unknown = tx
+        .prepare("SELECT name FROM temp.event_type_ids WHERE id IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    if !unknown.is_empty() {
+        log::warn!(
+            "Event types {:?} in {:?} are unknown to this version, their lines are merged as {}",
+            unknown,
+            path,
+            EventType::Unknown
+        );
+        tx.execute(
+            "UPDATE temp.event_type_ids SET id = $1 WHERE id IS NULL",
+            [EventType::Unknown as u32],
+        )?;
+    }
+
+    tx.execute(
+        "
+        INSERT INTO main.files
+            (id, path, relative_path, archive_path, timestamp, version, platform, level, predecessor_id,
+             first_line_num, patterns_source, size, modified, hash, line_count, database, device)
+        SELECT
+            id + $1, path, relative_path, archive_path, timestamp, version, platform, level, predecessor_id + $1,
+            first_line_num, patterns_source, size, modified, hash, line_count, COALESCE(database, $2),
+            COALESCE(device, $3)
+        FROM input.files",
+        params![file_offset, database, device],
+    )?;
+    tx.execute(
+        "
+        INSERT INTO main.sessions
+            (id, file_id, line_num, version, platform, os, build, commit_hash)
+        SELECT id + $1, file_id + $2, line_num, version, platform, os, build, commit_hash
+        FROM input.sessions",
+        params![session_offset, file_offset],
+    )?;
+    tx.execute(
+        "
+        INSERT INTO main.lines
+            (file_id, line_num, level, timestamp, domain, event_type, event_data, object_path, session_id, message,
+             source_offset)
+        SELECT
+            lines.file_id + $1, lines.line_num, lines.level, lines.timestamp, lines.domain, event_type_ids.id,
+            lines.event_data, lines.object_path, lines.session_id + $2, lines.message, lines.source_offset
+        FROM input.lines AS lines
+        JOIN temp.event_type_ids ON event_type_ids.input_id = lines.event_type",
+        params![file_offset, session_offset],
+    )?;
+    tx.execute(
+        "
+        INSERT INTO main.metadata (file_id, key, value)
+        SELECT file_id + $1, key, value FROM input.metadata",
+        [file_offset],
+    )?;
+    tx.execute(
+        "
+        INSERT INTO main.parse_errors (file_id, line_num, error, message, signature)
+        SELECT file_id + $1, line_num, error, message, signature FROM input.parse_errors",
+        [file_offset],
+    )?;
+    tx.execute_batch("DROP TABLE temp.event_type_ids")?;
+    tx.commit()?;
+    Ok(())
+}
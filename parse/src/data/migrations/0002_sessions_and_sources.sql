@@ -0,0 +1,121 @@
+-- Sessions, file metadata and parse errors, the source of each line and file, and the order of lines across files.
+-- Databases written before schema versioning have none of these, so they're filled in with defaults.
+
+-- Each version line starts a new session (app launch) within a file. A file may contain several sessions, of different
+-- CBL versions. Information about the CBL instance is captured from the version line, i.e. "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----"
+CREATE TABLE sessions(
+    id          INTEGER PRIMARY KEY NOT NULL,
+    file_id     INTEGER NOT NULL,
+    -- The line_num of the version line in the file
+    line_num    INTEGER NOT NULL,
+    -- CBL version (i.e. "3.2.0")
+    version     TEXT    NOT NULL,
+    -- CBL platform (i.e. ".NET"), if the version line contains it
+    platform    TEXT            ,
+    -- OS (i.e. "Microsoft Windows 10.0.22621"), if the version line contains it
+    os          TEXT            ,
+    -- Build number, if the version line contains it
+    build       INTEGER         ,
+    -- Combined CBL and LiteCore commit (i.e. "d8a2f91+3050eba"), if the version line contains it
+    commit_hash TEXT            ,
+    FOREIGN KEY (file_id)
+        REFERENCES files(id)
+);
+
+-- Key/value metadata from the header of each file, i.e. "serialNo=7" from the first line of binary logs.
+CREATE TABLE metadata(
+    file_id INTEGER NOT NULL,
+    key     TEXT    NOT NULL,
+    value   TEXT    NOT NULL,
+    PRIMARY KEY (file_id, key),
+    FOREIGN KEY (file_id)
+        REFERENCES files(id)
+);
+
+-- Lines which failed to parse. Lines which are not CBL lines, or are deliberately ignored, are not included.
+CREATE TABLE parse_errors(
+    file_id   INTEGER NOT NULL,
+    line_num  INTEGER NOT NULL,
+    -- The kind of error (i.e. "NoTimestamp", "NoSuchLevel")
+    error     TEXT    NOT NULL,
+    -- The full error message
+    message   TEXT    NOT NULL,
+    -- The line with IDs, numbers, etc. replaced by placeholders. Similar lines share the same signature, so
+    -- failures can be grouped with `GROUP BY signature`.
+    signature TEXT    NOT NULL,
+    PRIMARY KEY (file_id, line_num),
+    FOREIGN KEY (file_id)
+        REFERENCES files(id)
+);
+
+-- The session (app launch) the line belongs to. NULL for any lines before the first version line in the file.
+ALTER TABLE lines ADD COLUMN session_id INTEGER REFERENCES sessions(id);
+-- The text of the line after the header (timestamp, domain and level)
+ALTER TABLE lines ADD COLUMN message TEXT NOT NULL DEFAULT '';
+-- Where the line is in the source file. The byte offset for text logs, or the index of the entry for binary logs.
+ALTER TABLE lines ADD COLUMN source_offset INTEGER NOT NULL DEFAULT 0;
+-- The position of the line across all files, ordered by timestamp. Lines from the per-level files and rotated
+-- files are interleaved, so the lines either side of a line are what happened just before and after it.
+ALTER TABLE lines ADD COLUMN seq INTEGER;
+
+UPDATE lines SET seq = sequenced.seq
+FROM (
+    SELECT file_id, line_num, ROW_NUMBER() OVER (ORDER BY timestamp, file_id, line_num) AS seq
+    FROM lines
+) AS sequenced
+WHERE lines.file_id = sequenced.file_id AND lines.line_num = sequenced.line_num;
+
+CREATE UNIQUE INDEX lines_seq ON lines(seq);
+
+-- Columns can only be added to the end of a table, so `files` is rebuilt to keep `timestamp` after the paths.
+CREATE TABLE files_new(
+    -- `id` is unrelated to CBL, it's just a sequential ID.
+    id        INTEGER   PRIMARY KEY NOT NULL,
+    -- The path of the file, or of the archive which contains the file.
+    path      TEXT      NOT NULL,
+    -- The path of the file, or of the archive which contains the file, relative to the directory which was parsed.
+    relative_path TEXT      NOT NULL,
+    -- The path of the file within the archive, or NULL if the file isn't in an archive. The paths within nested
+    -- archives are separated by '!/', i.e. 'logs.tar.gz!/app/logs.zip!/cbl_info_1722271812929.txt'.
+    archive_path TEXT           ,
+    timestamp TIMESTAMP NOT NULL,
+    -- The CBL version detected for this file, which decides the patterns used to parse it.
+    version   TEXT      NOT NULL,
+    -- The name of the platform patterns used to parse this file (see `parse/src/patterns`).
+    platform  TEXT      NOT NULL,
+    -- The level of the lines in this file, from its name (i.e. 2 (Info) for cbl_info_1722271812929.cbllog). NULL if
+    -- the name doesn't give a level.
+    level     INTEGER           ,
+    -- The previous file of the same level, which was rotated out before this file was started. NULL for the first file
+    -- of each level.
+    predecessor_id INTEGER      ,
+    -- The line_num of the first line in this file, which follows on from the last line of the predecessor.
+    first_line_num INTEGER NOT NULL,
+    -- How the patterns were chosen (a PatternsSource): 0 from a version line in this file, 1 from another file in the
+    -- same directory as this file has no version line, or 2 forced by the user.
+    patterns_source INTEGER NOT NULL,
+    -- The size in bytes and the modification time of the file on disk (the archive, for a file within an archive) when
+    -- it was parsed. `modified` is NULL for logs which weren't read from disk. Used with `hash` to skip files which
+    -- haven't changed when updating the database.
+    size      INTEGER   NOT NULL,
+    modified  TIMESTAMP         ,
+    -- The SHA-256 of the contents of this file, as hex.
+    hash      TEXT      NOT NULL,
+    -- The number of lines in this file, including lines which weren't parsed, so the line_num following its last line
+    -- is first_line_num + line_count. NULL until the whole file has been parsed.
+    line_count INTEGER          ,
+    -- The database this file was merged from, and the device its logs are from. NULL unless this database was made by
+    -- merging others.
+    database  TEXT              ,
+    device    TEXT              ,
+    FOREIGN KEY (predecessor_id)
+        REFERENCES files(id)
+);
+
+INSERT INTO files_new
+    (id, path, relative_path, timestamp, version, platform, first_line_num, patterns_source, size, hash)
+SELECT id, path, path, timestamp, '', '', 0, 0, 0, ''
+FROM files;
+
+DROP TABLE files;
+ALTER TABLE files_new RENAME TO files;
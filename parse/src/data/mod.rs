@@ -2,5 +2,6 @@ mod database;
 mod types;
 pub(crate) mod util;
 
-pub use database::open_db;
+pub(crate) use database::{delete_files, insert_missing_event_types, merge_db, sequence_lines};
+pub use database::{open_db, SCHEMA_VERSION};
 pub use types::*;
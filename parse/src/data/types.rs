@@ -21,13 +21,94 @@ pub struct Line {
     pub event_type: EventType,
     pub event_data: Option<String>,
     pub object_path: Option<String>,
+    /// The `Session` this line belongs to, or `None` if it came before any version line.
+    pub session_id: Option<u32>,
+    /// The text of the line after the header (timestamp, domain and level).
+    pub message: String,
+    /// The byte offset of the line in a text log, or the index of the entry in a binary log.
+    pub offset: u64,
+    /// The position of the line across all parsed files, ordered by timestamp.
+    /// Only assigned once every file has been parsed, so `None` until the line is read back from the database.
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct File {
     pub id: u32,
+    /// The path of the file, or of the archive containing the file.
     pub path: String,
+    /// `path` relative to the directory which was parsed, or just the file name if a single file was parsed.
+    pub relative_path: String,
+    /// The path of the file within the archive at `path`, or `None` if the file isn't in an archive. The paths within
+    /// nested archives are separated by "!/", i.e. "logs.zip!/cbl_info_1722271812929.txt".
+    pub archive_path: Option<String>,
     pub timestamp: NaiveDateTime,
+    /// The CBL version of the patterns used to parse the file.
+    pub version: String,
+    /// The name of the platform patterns used to parse the file.
+    pub platform: String,
+    /// The level of the lines in the file, from its name, i.e. `Level::Info` for `cbl_info_1722271812929.cbllog`.
+    pub level: Option<Level>,
+    /// The previous file in the rotation of files of the same level.
+    pub predecessor_id: Option<u32>,
+    /// The line_num of the first line in the file. Line numbers continue from the end of the predecessor.
+    pub first_line_num: u32,
+    /// How the patterns used to parse the file were chosen.
+    pub patterns_source: PatternsSource,
+    /// The size in bytes of the file on disk, or of the archive containing it.
+    pub size: u64,
+    /// The modification time of the file on disk, or of the archive containing it. `None` for logs which weren't read
+    /// from disk.
+    pub modified: Option<NaiveDateTime>,
+    /// The SHA-256 of the contents of the file, as hex. Empty until the whole file has been parsed.
+    pub hash: String,
+    /// The number of lines in the file, including lines which weren't parsed. `None` until the whole file has been
+    /// parsed.
+    pub line_count: Option<u32>,
+    /// The path of the database the file was merged from, or `None` if it was parsed into this database.
+    pub database: Option<String>,
+    /// The name of the device the file's logs are from, or `None` if it was parsed into this database.
+    pub device: Option<String>,
+}
+
+/// A single run of CBL within a log file, starting at a version line. The version line is captured to give
+/// information about the CBL instance.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: u32,
+    pub file_id: u32,
+    /// The line of the file which holds the version line.
+    pub line_num: u32,
+    /// The CBL version, as written in the version line.
+    pub version: String,
+    /// The CBL platform, i.e. ".NET".
+    pub platform: Option<String>,
+    /// The OS, i.e. "Microsoft Windows 10.0.22621".
+    pub os: Option<String>,
+    pub build: Option<u32>,
+    /// The combined CBL and LiteCore commit string, i.e. "d8a2f91+3050eba".
+    pub commit: Option<String>,
+}
+
+/// A key/value pair from the header of a log file, i.e. `serialNo=7` in binary logs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub file_id: u32,
+    pub key: String,
+    pub value: String,
+}
+
+/// A line which failed to parse.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseError {
+    pub file_id: u32,
+    pub line_num: u32,
+    /// The kind of `Error` the line failed with, i.e. "NoTimestamp".
+    pub error: String,
+    /// The full error message.
+    pub message: String,
+    /// The line reduced to a signature, with IDs, numbers etc. replaced, so similar lines can be grouped.
+    pub signature: String,
 }
 
 #[derive(Hash, Debug, Copy, Clone, Eq, PartialEq, Serialize)]
@@ -42,9 +123,32 @@ pub enum Level {
 
 impl_display_debug!(Level);
 
+/// How the patterns for a file were chosen.
+#[derive(Hash, Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[repr(u32)]
+pub enum PatternsSource {
+    /// Detected from a version line in the file.
+    VersionLine,
+    /// The file has no version line (i.e. it was rotated, or its start was truncated), so the patterns of another file
+    /// in the same directory were used.
+    Sibling,
+    /// Forced by `Options::cbl_version` and `Options::platform`.
+    Override,
+}
+
+impl_display_debug!(PatternsSource);
+
+impl Line {
+    /// Lines are ordered by timestamp. Lines with the same timestamp are ordered by file, then by line within the file,
+    /// which is the same order as `seq`.
+    fn sort_key(&self) -> (NaiveDateTime, u32, u32) {
+        (self.timestamp, self.file_id, self.line_num)
+    }
+}
+
 impl PartialEq<Self> for Line {
     fn eq(&self, other: &Self) -> bool {
-        self.level == other.level && self.line_num == other.line_num
+        self.sort_key() == other.sort_key()
     }
 }
 
@@ -58,7 +162,7 @@ impl PartialOrd<Self> for Line {
 
 impl Ord for Line {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.timestamp.cmp(&other.timestamp)
+        self.sort_key().cmp(&other.sort_key())
     }
 }
 
@@ -88,14 +192,21 @@ impl From<u32> for Level {
     }
 }
 
+impl From<u32> for PatternsSource {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::VersionLine,
+            1 => Self::Sibling,
+            2 => Self::Override,
+            _ => panic!("Invalid patterns source value: {}", value),
+        }
+    }
+}
+
+/// IDs which aren't known to this build, i.e. from a database written by a newer build, are `EventType::Unknown`.
 impl From<u32> for EventType {
     fn from(value: u32) -> Self {
-        assert!(
-            value < enum_iterator::cardinality::<EventType>() as u32,
-            "Invalid event type value: {}",
-            value
-        );
-        unsafe { std::mem::transmute::<u32, EventType>(value) }
+        EventType::from_id(value).unwrap_or(EventType::Unknown)
     }
 }
 
@@ -125,8 +236,9 @@ impl Insertable for &Line {
         tx.execute(
             "
             INSERT INTO lines
-                (file_id, line_num, level, timestamp, domain, event_type, event_data, object_path)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                (file_id, line_num, level, timestamp, domain, event_type, event_data, object_path, session_id,
+                 message, source_offset)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
             params![
                 self.file_id,
                 self.line_num,
@@ -136,6 +248,9 @@ impl Insertable for &Line {
                 self.event_type as u32,
                 self.event_data,
                 self.object_path,
+                self.session_id,
+                self.message,
+                self.offset,
             ],
         )
         .map_err(Error::Sqlite)
@@ -148,9 +263,86 @@ impl Insertable for &File {
         tx.execute(
             "
             INSERT INTO files
-                (id, path, timestamp)
+                (id, path, relative_path, archive_path, timestamp, version, platform, level, predecessor_id,
+                 first_line_num, patterns_source, size, modified, hash, line_count, database, device)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
+            params![
+                self.id,
+                self.path,
+                self.relative_path,
+                self.archive_path,
+                self.timestamp,
+                self.version,
+                self.platform,
+                self.level.map(|level| level as u32),
+                self.predecessor_id,
+                self.first_line_num,
+                self.patterns_source as u32,
+                self.size,
+                self.modified,
+                self.hash,
+                self.line_count,
+                self.database,
+                self.device,
+            ],
+        )
+        .map_err(Error::Sqlite)
+        .map(|_| ())
+    }
+}
+
+impl Insertable for &Session {
+    fn db_insert(self, tx: &mut Transaction) -> Result<()> {
+        tx.execute(
+            "
+            INSERT INTO sessions
+                (id, file_id, line_num, version, platform, os, build, commit_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            params![
+                self.id,
+                self.file_id,
+                self.line_num,
+                self.version,
+                self.platform,
+                self.os,
+                self.build,
+                self.commit,
+            ],
+        )
+        .map_err(Error::Sqlite)
+        .map(|_| ())
+    }
+}
+
+impl Insertable for &Metadata {
+    fn db_insert(self, tx: &mut Transaction) -> Result<()> {
+        // A header may repeat a key, in which case the last value is kept.
+        tx.execute(
+            "
+            INSERT OR REPLACE INTO metadata
+                (file_id, key, value)
             VALUES ($1, $2, $3)",
-            params![self.id, self.path, self.timestamp],
+            params![self.file_id, self.key, self.value],
+        )
+        .map_err(Error::Sqlite)
+        .map(|_| ())
+    }
+}
+
+impl Insertable for &ParseError {
+    fn db_insert(self, tx: &mut Transaction) -> Result<()> {
+        tx.execute(
+            "
+            INSERT INTO parse_errors
+                (file_id, line_num, error, message, signature)
+            VALUES ($1, $2, $3, $4, $5)",
+            params![
+                self.file_id,
+                self.line_num,
+                self.error,
+                self.message,
+                self.signature
+            ],
         )
         .map_err(Error::Sqlite)
         .map(|_| ())
@@ -159,8 +351,6 @@ impl Insertable for &File {
 
 impl Insertable for &EventType {
     fn db_insert(self, tx: &mut Transaction) -> Result<()> {
-        //id: unsafe { std::mem::transmute::<EventType, i32>(value) },
-        //name: value.to_string(),
         tx.execute(
             "
             INSERT INTO event_types
@@ -178,7 +368,58 @@ impl FromRow for File {
         Ok(Self {
             id: row.get(0)?,
             path: row.get(1)?,
-            timestamp: row.get(2)?,
+            relative_path: row.get(2)?,
+            archive_path: row.get(3)?,
+            timestamp: row.get(4)?,
+            version: row.get(5)?,
+            platform: row.get(6)?,
+            level: row.get::<_, Option<u32>>(7)?.map(Level::from),
+            predecessor_id: row.get(8)?,
+            first_line_num: row.get(9)?,
+            patterns_source: PatternsSource::from(row.get::<_, u32>(10)?),
+            size: row.get(11)?,
+            modified: row.get(12)?,
+            hash: row.get(13)?,
+            line_count: row.get(14)?,
+            database: row.get(15)?,
+            device: row.get(16)?,
+        })
+    }
+}
+
+impl FromRow for Session {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            file_id: row.get(1)?,
+            line_num: row.get(2)?,
+            version: row.get(3)?,
+            platform: row.get(4)?,
+            os: row.get(5)?,
+            build: row.get(6)?,
+            commit: row.get(7)?,
+        })
+    }
+}
+
+impl FromRow for Metadata {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            file_id: row.get(0)?,
+            key: row.get(1)?,
+            value: row.get(2)?,
+        })
+    }
+}
+
+impl FromRow for ParseError {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            file_id: row.get(0)?,
+            line_num: row.get(1)?,
+            error: row.get(2)?,
+            message: row.get(3)?,
+            signature: row.get(4)?,
         })
     }
 }
@@ -194,6 +435,10 @@ impl FromRow for Line {
             event_type: EventType::from(row.get::<_, u32>(5)?),
             event_data: row.get(6)?,
             object_path: row.get(7)?,
+            session_id: row.get(8)?,
+            message: row.get(9)?,
+            offset: row.get(10)?,
+            seq: row.get(11)?,
         })
     }
 }
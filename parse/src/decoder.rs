@@ -2,53 +2,123 @@ use core::fmt;
 use core::fmt::Write;
 use std::{
     collections::BTreeMap,
-    io::{BufRead, BufReader, Read, Seek},
+    io::{BufReader, Read, Seek, SeekFrom},
     path::Path,
 };
 
 use chrono::{DateTime, NaiveDateTime, TimeDelta};
-
-use crate::{Error, Result};
-
-const MAGIC_NUMBER: [u8; 4] = [0xCF, 0xB2, 0xAB, 0x1B];
-const FORMAT_VERSION: u8 = 1;
-const LEVEL_NAMES: [&str; 5] = ["Debug", "Verbose", "Info", "Warning", "Error"];
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{data::Level, Error, Result};
+
+pub(crate) const MAGIC_NUMBER: [u8; 4] = [0xCF, 0xB2, 0xAB, 0x1B];
+pub(crate) const FORMAT_VERSION: u8 = 1;
+/// Levels by their value in binary logs.
+pub(crate) const LEVELS: [Level; 5] = [
+    Level::Debug,
+    Level::Verbose,
+    Level::Info,
+    Level::Warning,
+    Level::Error,
+];
 const TS_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
 const TICKS_PER_SECOND: u64 = 1_000_000;
+/// When searching for a valid entry after corruption, reject entries more than a day after the previous entry.
+const MAX_RESYNC_TICKS: u64 = 86_400 * TICKS_PER_SECOND;
 
+/// Whether the file at the given path is a binary log file.
 pub fn is_encoded(path: &Path) -> Result<bool> {
-    let mut file = std::fs::File::open(path)?;
+    starts_with_magic_number(&mut std::fs::File::open(path)?)
+}
+
+/// Whether the data from `reader` starts with the magic number of binary logs. The reader is advanced past it.
+pub(crate) fn starts_with_magic_number(reader: &mut impl Read) -> Result<bool> {
     let mut magic = [0_u8; 4];
-    file.read_exact(&mut magic)?;
-    Ok(magic == MAGIC_NUMBER)
+    match reader.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAGIC_NUMBER),
+        // Too short to be a binary log file
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+lazy_static! {
+    /// Binary logs begin with a header line of comma-separated key/value pairs,
+    /// i.e. `---- serialNo=1,logDirectory=/tmp/logs/,fileLogLevel=0 ----`
+    static ref HEADER_REGEX: Regex = Regex::new(r#"---- (?<fields>\w+=.*) ----$"#).unwrap();
 }
 
-pub fn decode_lines(path: &Path) -> Result<Vec<String>> {
-    let file = std::fs::File::open(path)?;
-    let buf_reader = BufReader::new(file);
-    let mut decoder = Decoder::new(buf_reader)?;
-    decoder.lines().collect()
+/// Parse the key/value pairs from a header line, or `None` if the line isn't a header.
+pub(crate) fn parse_header(line: &str) -> Option<Vec<(String, String)>> {
+    let caps = HEADER_REGEX.captures(line)?;
+    let fields = caps.name("fields")?.as_str();
+
+    Some(
+        fields
+            .split(',')
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    )
 }
 
-struct Decoder<R>
+/// A streaming reader of binary logs, which iterates over the entries in the logs.
+/// Entries are only read as the iterator is advanced.
+///
+/// Malformed input never panics. An invalid header is an `Error::InvalidBinaryLogs` from `Decoder::new`. Corrupt
+/// regions are skipped, yielding an `Error::CorruptBinaryLogs` for each, and iteration continues from the next valid
+/// entry.
+pub struct Decoder<R>
 where
-    R: BufRead + Seek,
+    R: Read + Seek,
 {
-    reader: R,
+    reader: BufReader<R>,
+    /// The length of the input in bytes.
+    len: u64,
     pointer_size: u8,
     start_time: NaiveDateTime,
     elapsed_ticks: u64,
     tokens: Vec<String>,
     objects: BTreeMap<u64, String>,
+    /// The IDs of objects in the order they were added, so objects can be removed when restoring a `Checkpoint`.
+    object_ids: Vec<u64>,
+    /// The number of bytes skipped due to corruption. Tokens may have been defined in the skipped bytes.
+    skipped_bytes: u64,
+    /// Whether an entry is being read speculatively while searching for a valid entry.
+    resyncing: bool,
+    /// Whether the input is still being written, so may grow, and may end part way through an entry.
+    following: bool,
+    metadata: Vec<(String, String)>,
+}
+
+/// The decoder state at the start of an entry, so a corrupt entry can be undone.
+struct Checkpoint {
+    elapsed_ticks: u64,
+    token_count: usize,
+    object_count: usize,
+}
+
+impl Decoder<std::fs::File> {
+    /// Open the binary log file at the given path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(std::fs::File::open(path)?)
+    }
 }
 
 impl<R> Decoder<R>
 where
-    R: BufRead + Seek,
+    R: Read + Seek,
 {
-    fn new(mut reader: R) -> Result<Self> {
+    /// Read the header of the binary logs from the given reader.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(reader);
         let mut header = [0_u8; 6];
-        reader.read_exact(&mut header)?;
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| Error::InvalidBinaryLogs("Incomplete header".into(), 0))?;
         if header[..4] != MAGIC_NUMBER {
             return Err(Error::InvalidBinaryLogs("Invalid header".into(), 0));
         }
@@ -65,8 +135,11 @@ where
         if pointer_size != 4 && pointer_size != 8 {
             return Err(Error::InvalidBinaryLogs("Invalid header".into(), 0));
         }
-        let Some(start_time) = DateTime::from_timestamp(varint::read(&mut reader)? as i64, 0)
-        else {
+        let start_time = varint::read(&mut reader)
+            .ok()
+            .and_then(|seconds| i64::try_from(seconds).ok())
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0));
+        let Some(start_time) = start_time else {
             return Err(Error::InvalidBinaryLogs(
                 "Invalid timestamp in header".into(),
                 0,
@@ -77,56 +150,184 @@ where
             start_time.format(TS_FORMAT),
             pointer_size
         );
-        Ok(Self {
+        let mut decoder = Self {
             reader,
+            len,
             pointer_size,
             start_time: start_time.naive_utc(),
             elapsed_ticks: 0,
             tokens: vec![],
             objects: BTreeMap::new(),
-        })
+            object_ids: vec![],
+            skipped_bytes: 0,
+            resyncing: false,
+            following: false,
+            metadata: vec![],
+        };
+        decoder.metadata = decoder.peek_metadata()?;
+        Ok(decoder)
     }
 
-    fn entries(&mut self) -> impl Iterator<Item = Result<DecoderEntry>> + '_ {
-        std::iter::from_fn(move || match self.read_entry() {
-            Ok(Some(entry)) => Some(Ok(entry)),
-            Ok(None) => None,
-            Err(err) => Some(Err(err)),
-        })
+    /// The size of pointers in bytes (4 or 8) on the device which wrote the logs.
+    pub fn pointer_size(&self) -> u8 {
+        self.pointer_size
+    }
+
+    /// The time the logs were started, which entry timestamps are relative to.
+    pub fn start_time(&self) -> NaiveDateTime {
+        self.start_time
+    }
+
+    /// The key/value pairs from the header entry at the start of the logs,
+    /// i.e. `serialNo`, `logDirectory`, `fileLogLevel`.
+    pub fn metadata(&self) -> &[(String, String)] {
+        &self.metadata
     }
 
-    fn lines(&mut self) -> impl Iterator<Item = Result<String>> + '_ {
-        self.entries()
-            .map(|entry| entry.map(|entry| entry.to_string()))
+    /// Follow logs which are still being written. When following, reaching the end of the input ends iteration without
+    /// treating an incomplete entry at the end as corrupt, and the next call to `next` continues from that entry with
+    /// any data written since. Tokens and objects are kept, so entries which refer to earlier ones can still be read.
+    pub fn set_following(&mut self, following: bool) {
+        self.following = following;
     }
 
-    fn read_entry(&mut self) -> Result<Option<DecoderEntry>> {
-        let Ok(timestamp) = self.read_timestamp() else {
-            return Ok(None);
+    /// Read the header entry, if there is one, without advancing the decoder.
+    fn peek_metadata(&mut self) -> Result<Vec<(String, String)>> {
+        let start = self.reader.stream_position()?;
+        let checkpoint = self.checkpoint();
+        let entry = self.read_entry().ok();
+        self.restore(&checkpoint);
+        self.seek_to(start)?;
+        Ok(entry
+            .and_then(|entry| parse_header(&entry.message))
+            .unwrap_or_default())
+    }
+
+    /// Read the next entry, or `None` at the end of the input.
+    /// If the entry is corrupt, skip ahead to the next valid entry and return an `Error::CorruptBinaryLogs` describing
+    /// the skipped region. Decoding continues from the valid entry on the next call.
+    fn next_entry(&mut self) -> Option<Result<Entry>> {
+        let start = match self.reader.stream_position() {
+            Ok(start) => start,
+            Err(err) => return Some(Err(Error::Io(err))),
         };
+        if self.following && start >= self.len {
+            // Pick up any data written since the length was last read.
+            match self.refresh_len(start) {
+                Ok(len) => self.len = len,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        if start >= self.len {
+            return None;
+        }
+
+        let checkpoint = self.checkpoint();
+        match self.read_entry() {
+            Ok(entry) => Some(Ok(entry)),
+            Err(_) if self.following && self.remaining().is_ok_and(|remaining| remaining == 0) => {
+                // The entry is still being written, so read it again once the rest has been written. The input is
+                // treated as ending before the entry, so its length is checked again before the next read.
+                self.restore(&checkpoint);
+                if let Err(err) = self.reader.seek(SeekFrom::Start(start)) {
+                    return Some(Err(Error::Io(err)));
+                }
+                self.len = start;
+                None
+            }
+            Err(err) => {
+                self.restore(&checkpoint);
+                log::debug!("Corrupt binary log entry at {}: {}", start, err);
+                let end = match self.resync(start + 1) {
+                    Ok(end) => end,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.skipped_bytes += end - start;
+                Some(Err(Error::CorruptBinaryLogs(
+                    err.to_string(),
+                    start,
+                    end - start,
+                )))
+            }
+        }
+    }
+
+    /// Find the first position from `from` where two consecutive entries (or one entry at the end of the input) can be
+    /// read, and seek to it. Returns the position, which is the end of the input if no valid entry was found.
+    fn resync(&mut self, from: u64) -> Result<u64> {
+        for pos in from..self.len {
+            self.seek_to(pos)?;
+            let checkpoint = self.checkpoint();
+            self.resyncing = true;
+            // Tokens defined in the skipped region may be referenced by the entries being tried.
+            let skipped = pos + 1 - from;
+            self.skipped_bytes += skipped;
+            let valid = self.read_entry().is_ok()
+                && (self.reader.stream_position()? >= self.len || self.read_entry().is_ok());
+            self.skipped_bytes -= skipped;
+            self.resyncing = false;
+            self.restore(&checkpoint);
+            if valid {
+                log::debug!("Resynced binary logs at {}", pos);
+                self.seek_to(pos)?;
+                return Ok(pos);
+            }
+        }
+        self.seek_to(self.len)?;
+        Ok(self.len)
+    }
+
+    /// The current length of the input, leaving the reader at `pos`.
+    fn refresh_len(&mut self, pos: u64) -> Result<u64> {
+        let len = self.reader.seek(SeekFrom::End(0))?;
+        self.reader.seek(SeekFrom::Start(pos))?;
+        Ok(len)
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            elapsed_ticks: self.elapsed_ticks,
+            token_count: self.tokens.len(),
+            object_count: self.object_ids.len(),
+        }
+    }
+
+    fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.elapsed_ticks = checkpoint.elapsed_ticks;
+        self.tokens.truncate(checkpoint.token_count);
+        for id in self.object_ids.drain(checkpoint.object_count..) {
+            self.objects.remove(&id);
+        }
+    }
+
+    /// Seek to the given position, keeping the read buffer where possible.
+    fn seek_to(&mut self, pos: u64) -> Result<()> {
+        let current = self.reader.stream_position()?;
+        self.reader.seek_relative(pos as i64 - current as i64)?;
+        Ok(())
+    }
+
+    fn read_entry(&mut self) -> Result<Entry> {
+        let timestamp = self.read_timestamp()?;
         log::trace!("Read timestamp {}", timestamp.format(TS_FORMAT));
-        let level = self.read_byte()? as i8;
+        let level = self.read_byte()?;
         log::trace!("Read level {}", level);
-        let level = if level > 0 {
-            LEVEL_NAMES.get(level as usize).ok_or_else(|| {
-                self.create_err(format!("No known log level with discriminant {}", level))
-            })?
-        } else {
-            ""
-        };
+        let level = *LEVELS.get(level as usize).ok_or_else(|| {
+            self.create_err(format!("No known log level with discriminant {}", level))
+        })?;
         let domain = self.read_tokenized_string()?.clone();
         log::trace!("Read domain '{}'", domain);
         let object = self.read_object()?;
         log::trace!("Read object '{}'", object.as_ref().unwrap_or(&"".into()));
         let message = self.read_message()?;
         log::trace!("Read message '{}'", message);
-        Ok(Some(DecoderEntry {
+        Ok(Entry {
             timestamp,
             domain,
             level,
             object,
             message,
-        }))
+        })
     }
 
     fn read_message(&mut self) -> Result<String> {
@@ -135,38 +336,40 @@ where
             log::trace!("Read format string: '{}'", format_string);
             format_string.chars().collect()
         };
+        // A format string which ends part way through a specifier reads as NULL, an unknown specifier.
+        let format_char = |i: usize| format_chars.get(i).copied().unwrap_or('\0');
         let mut message = String::new();
 
         let mut i: usize = 0;
 
         while i < format_chars.len() {
-            if format_chars[i] == '\0' {
+            if format_char(i) == '\0' {
                 log::trace!("Read NULL terminator");
                 break;
             }
-            if format_chars[i] != '%' {
-                message.push(format_chars[i]);
+            if format_char(i) != '%' {
+                message.push(format_char(i));
                 i += 1;
                 continue;
             }
 
-            let is_minus = format_chars[i + 1] == '-';
+            let is_minus = format_char(i + 1) == '-';
             i = if is_minus { i + 2 } else { i + 1 };
 
-            while "#0- +'".contains(format_chars[i]) {
+            while "#0- +'".contains(format_char(i)) {
                 i += 1;
             }
-            while format_chars[i].is_ascii_digit() {
+            while format_char(i).is_ascii_digit() {
                 i += 1;
             }
 
-            let is_dot_star = if format_chars[i] == '.' {
+            let is_dot_star = if format_char(i) == '.' {
                 i += 1;
-                if format_chars[i] == '*' {
+                if format_char(i) == '*' {
                     i += 1;
                     true
                 } else {
-                    while format_chars[i].is_ascii_digit() {
+                    while format_char(i).is_ascii_digit() {
                         i += 1;
                     }
                     false
@@ -175,17 +378,18 @@ where
                 false
             };
 
-            while "hljtzq".contains(format_chars[i]) {
+            while "hljtzq".contains(format_char(i)) {
                 i += 1;
             }
 
-            let c = format_chars[i];
+            let c = format_char(i);
             match c {
                 'c' | 'd' | 'i' => {
                     let is_negative = self.read_byte()? > 0;
-                    let value: i64 = varint::read(&mut self.reader)?
-                        .try_into()
-                        .expect("Overflow in numeric parameter!");
+                    let value = self.read_varint()?;
+                    let value = i64::try_from(value).map_err(|_| {
+                        self.create_err(format!("Overflow in numeric parameter {}", value))
+                    })?;
                     let value = if is_negative { -value } else { value };
                     if c == 'c' {
                         message.push(value as u8 as char);
@@ -194,17 +398,15 @@ where
                     }
                 }
                 'x' | 'X' => {
-                    let value = varint::read(&mut self.reader)?;
+                    let value = self.read_varint()?;
                     message.push_str(&format!("{:02x}", value));
                 }
                 'u' => {
-                    let value = varint::read(&mut self.reader)?;
+                    let value = self.read_varint()?;
                     message.push_str(&value.to_string());
                 }
                 'e' | 'E' | 'f' | 'F' | 'g' | 'G' | 'a' | 'A' => {
-                    let mut buf = [0_u8; 8];
-                    self.reader.read_exact(&mut buf)?;
-                    let value = f64::from_le_bytes(buf);
+                    let value = f64::from_le_bytes(self.read_array()?);
                     message.push_str(&value.to_string());
                 }
                 '@' | 's' if is_minus && !is_dot_star => {
@@ -212,30 +414,31 @@ where
                     message.push_str(string);
                 }
                 '@' | 's' => {
-                    let length = varint::read(&mut self.reader)? as usize;
-                    let mut string = "0".repeat(length);
-                    self.reader.read_exact(unsafe { string.as_bytes_mut() })?;
+                    let length = self.read_varint()?;
+                    if length > self.remaining()? {
+                        return Err(self.create_err(format!(
+                            "String parameter length {} is past the end of the logs",
+                            length
+                        )));
+                    }
+                    let mut bytes = vec![0_u8; length as usize];
+                    self.reader
+                        .read_exact(&mut bytes)
+                        .map_err(|err| self.map_err(err))?;
                     if is_minus {
-                        string = string
-                            .into_bytes()
-                            .into_iter()
-                            .fold(String::new(), |mut s, b| {
-                                let _ = write!(s, "{:02x}", b);
-                                s
-                            });
+                        for b in bytes {
+                            let _ = write!(message, "{:02x}", b);
+                        }
+                    } else {
+                        message.push_str(&String::from_utf8_lossy(&bytes));
                     }
-                    message.push_str(&string);
                 }
                 'p' if self.pointer_size == 8 => {
-                    let mut buf = [0_u8; 8];
-                    self.reader.read_exact(&mut buf)?;
-                    let value = u64::from_le_bytes(buf);
+                    let value = u64::from_le_bytes(self.read_array()?);
                     message.push_str(&format!("{:#016x}", value));
                 }
                 'p' if self.pointer_size == 4 => {
-                    let mut buf = [0_u8; 4];
-                    self.reader.read_exact(&mut buf)?;
-                    let value = u32::from_le_bytes(buf);
+                    let value = u32::from_le_bytes(self.read_array()?);
                     message.push_str(&format!("{:#08x}", value));
                 }
                 '%' => {
@@ -252,27 +455,50 @@ where
     }
 
     fn read_timestamp(&mut self) -> Result<NaiveDateTime> {
-        self.elapsed_ticks += varint::read(&mut self.reader).map_err(|err| self.map_err(err))?;
-        let timestamp = self.start_time
-            + TimeDelta::seconds(
-                (self.elapsed_ticks / TICKS_PER_SECOND)
-                    .try_into()
-                    .expect("Overflow in timestamp calculation!"),
-            )
-            + TimeDelta::microseconds(
-                (self.elapsed_ticks % TICKS_PER_SECOND)
-                    .try_into()
-                    .expect("Overflow in timestamp calculation!"),
-            );
-        Ok(timestamp)
+        let ticks = self.read_varint()?;
+        if self.resyncing && ticks > MAX_RESYNC_TICKS {
+            return Err(self.create_err("Timestamp is too far after the previous entry".into()));
+        }
+        self.elapsed_ticks = self
+            .elapsed_ticks
+            .checked_add(ticks)
+            .ok_or_else(|| self.create_err("Overflow in timestamp".into()))?;
+        let elapsed = i64::try_from(self.elapsed_ticks / TICKS_PER_SECOND)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|seconds| {
+                seconds.checked_add(&TimeDelta::microseconds(
+                    (self.elapsed_ticks % TICKS_PER_SECOND) as i64,
+                ))
+            });
+        elapsed
+            .and_then(|elapsed| self.start_time.checked_add_signed(elapsed))
+            .ok_or_else(|| self.create_err("Overflow in timestamp".into()))
+    }
+
+    /// The number of bytes left in the input.
+    fn remaining(&mut self) -> Result<u64> {
+        Ok(self.len.saturating_sub(self.reader.stream_position()?))
     }
 
     fn read_byte(&mut self) -> Result<u8> {
         self.reader.read_byte().map_err(|err| self.map_err(err))
     }
 
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0_u8; N];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|err| self.map_err(err))?;
+        Ok(buf)
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        varint::read(&mut self.reader).map_err(|err| self.map_err(err))
+    }
+
     fn read_object(&mut self) -> Result<Option<String>> {
-        let object_id = varint::read(&mut self.reader).map_err(|err| self.map_err(err))?;
+        let object_id = self.read_varint()?;
         if object_id == 0 {
             Ok(None)
         } else if let Some(object) = self.objects.get(&object_id) {
@@ -280,12 +506,21 @@ where
         } else {
             let object = self.read_string()?;
             self.objects.insert(object_id, object.clone());
+            self.object_ids.push(object_id);
             Ok(Some(object))
         }
     }
 
     fn read_tokenized_string(&mut self) -> Result<&String> {
-        let token_id = varint::read(&mut self.reader).map_err(|err| self.map_err(err))? as usize;
+        let token_id = self.read_varint()? as usize;
+        // Tokens defined in a skipped region are lost, so new tokens after it will have IDs past the end of `tokens`.
+        // Fill the gap with placeholders, so the new token can still be read.
+        let gap = token_id.saturating_sub(self.tokens.len());
+        if gap > 0 && (gap as u64) <= self.skipped_bytes {
+            for id in self.tokens.len()..token_id {
+                self.tokens.push(format!("<lost token {}>", id));
+            }
+        }
         match token_id.cmp(&self.tokens.len()) {
             std::cmp::Ordering::Less => Ok(&self.tokens[token_id]),
             std::cmp::Ordering::Equal => {
@@ -297,17 +532,20 @@ where
         }
     }
 
-    /// Read a null-terminated string from the reader.
+    /// Read a null-terminated string from the reader. Invalid UTF-8 is replaced.
     fn read_string(&mut self) -> Result<String> {
-        let mut string = String::with_capacity(20);
+        let mut string = Vec::with_capacity(20);
         loop {
-            let byte = self.read_byte().map_err(|err| self.map_err(err))?;
+            let byte = self.read_byte()?;
             if byte == 0 {
                 break;
             }
-            string.push(byte as char);
+            if self.resyncing && byte < 0x20 && byte != b'\t' {
+                return Err(self.create_err("Unprintable character in string".into()));
+            }
+            string.push(byte);
         }
-        Ok(string)
+        Ok(String::from_utf8_lossy(&string).into_owned())
     }
 
     #[inline]
@@ -321,33 +559,109 @@ where
     }
 }
 
-struct DecoderEntry {
-    timestamp: NaiveDateTime,
-    domain: String,
-    level: &'static str,
-    object: Option<String>,
-    message: String,
+impl<R> Iterator for Decoder<R>
+where
+    R: Read + Seek,
+{
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+    }
 }
 
-impl fmt::Display for DecoderEntry {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let timestamp = self.timestamp.format(TS_FORMAT);
+/// Merge the entries of several binary logs, i.e. the per-level files of one log directory, into a single stream
+/// ordered by timestamp. Entries with equal timestamps are taken in the order of `decoders`.
+/// Errors from any of the decoders are returned as soon as they are read.
+pub fn merge<R>(decoders: impl IntoIterator<Item = Decoder<R>>) -> Merge<R>
+where
+    R: Read + Seek,
+{
+    Merge {
+        decoders: decoders
+            .into_iter()
+            .map(|decoder| (decoder, None))
+            .collect(),
+    }
+}
+
+/// An iterator over the entries of several binary logs in timestamp order. See `merge`.
+pub struct Merge<R>
+where
+    R: Read + Seek,
+{
+    /// Each decoder with its next entry, which has been read but not yet returned.
+    decoders: Vec<(Decoder<R>, Option<Entry>)>,
+}
+
+impl<R> Iterator for Merge<R>
+where
+    R: Read + Seek,
+{
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut i = 0;
+        while i < self.decoders.len() {
+            let (decoder, next) = &mut self.decoders[i];
+            if next.is_none() {
+                match decoder.next() {
+                    Some(Ok(entry)) => *next = Some(entry),
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {
+                        self.decoders.remove(i);
+                        continue;
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // `min_by_key` returns the first of equal elements, so ties keep the order of the decoders.
+        let (_, next) = self
+            .decoders
+            .iter_mut()
+            .min_by_key(|(_, next)| next.as_ref().map(|entry| entry.timestamp))?;
+        next.take().map(Ok)
+    }
+}
+
+/// A single entry from binary logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub timestamp: NaiveDateTime,
+    pub level: Level,
+    /// The log domain (i.e. "DB", "Sync"). Empty for the header entries at the start of the logs.
+    pub domain: String,
+    /// The path of the object which logged the entry, with a trailing '/' (i.e. "/Repl#76/Puller#123/").
+    pub object: Option<String>,
+    pub message: String,
+}
+
+impl Entry {
+    /// The text of the entry following the level, as it would appear in a text log.
+    pub fn body(&self) -> String {
         if let Some(object) = self.object.as_deref() {
-            write!(
-                f,
-                "{} {} {} Obj={} {}",
-                timestamp, self.domain, self.level, object, self.message
-            )
+            format!("Obj={} {}", object, self.message)
         } else {
-            write!(
-                f,
-                "{} {} {} {}",
-                timestamp, self.domain, self.level, self.message
-            )
+            self.message.clone()
         }
     }
 }
 
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.timestamp.format(TS_FORMAT),
+            self.domain,
+            self.level,
+            self.body()
+        )
+    }
+}
+
 trait ReadByte: Read {
     #[inline]
     fn read_byte(&mut self) -> std::io::Result<u8> {
@@ -359,10 +673,10 @@ trait ReadByte: Read {
 
 impl<T: Read + ?Sized> ReadByte for T {}
 
-mod varint {
+pub(crate) mod varint {
     use crate::decoder::ReadByte;
     use crate::{Error, Result};
-    use std::io::Read;
+    use std::io::{Read, Write};
 
     const MAX_LEN: usize = 10;
 
@@ -374,6 +688,10 @@ mod varint {
 
         for i in 0..MAX_LEN {
             let byte = reader.read_byte()?;
+            // The last byte can only hold the top bit of a u64
+            if i == MAX_LEN - 1 && byte > 1 {
+                return Err(Error::InvalidVarint);
+            }
             res |= u64::from(byte & 0x7F) << (7 * i);
             if byte < 0x80 {
                 return Ok(res);
@@ -383,24 +701,31 @@ mod varint {
         Err(Error::InvalidVarint)
     }
 
+    /// Write the given value as a varint, returning the number of bytes written.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write<W>(writer: &mut W, value: u64) -> std::io::Result<usize>
+    where
+        W: Write + ?Sized,
+    {
+        let mut buf = [0_u8; MAX_LEN];
+        let mut value = value;
+        let mut bytes_written: usize = 0;
+        while value >= 0x80 {
+            buf[bytes_written] = (value & 0xFF) as u8 | 0x80;
+            value >>= 7;
+            bytes_written += 1;
+        }
+        buf[bytes_written] = value as u8;
+        bytes_written += 1;
+        writer.write_all(&buf[..bytes_written])?;
+        Ok(bytes_written)
+    }
+
     #[cfg(test)]
     mod test {
         use crate::decoder::varint;
         use std::io::Cursor;
 
-        #[allow(clippy::cast_possible_truncation)]
-        pub fn write(out: &mut [u8], value: u64) -> usize {
-            let mut value = value;
-            let mut bytes_written: usize = 0;
-            while value >= 0x80 {
-                out[bytes_written] = (value & 0xFF) as u8 | 0x80;
-                value >>= 7;
-                bytes_written += 1;
-            }
-            out[bytes_written] = value as u8;
-            bytes_written + 1
-        }
-
         // The number of bytes required to write a varint with the given value
         pub const fn size_required(value: u64) -> usize {
             if value == 0 {
@@ -412,8 +737,9 @@ mod varint {
 
         fn varint_test(val: u64) {
             let size_required = size_required(val);
-            let mut buf: Vec<u8> = vec![0; size_required];
-            let _written = write(&mut buf, val);
+            let mut buf: Vec<u8> = Vec::with_capacity(size_required);
+            let written = varint::write(&mut buf, val).unwrap();
+            assert_eq!(written, size_required);
             println!("Wrote varint {:02x?}", &buf);
             let mut cursor = Cursor::new(buf);
             let out_val = varint::read(&mut cursor).unwrap();
@@ -426,6 +752,7 @@ mod varint {
             varint_test(100_000);
             varint_test(603);
             varint_test(87);
+            varint_test(u64::MAX);
         }
     }
 }
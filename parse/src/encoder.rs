@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    data::Level,
+    decoder::{varint, Entry, FORMAT_VERSION, LEVELS, MAGIC_NUMBER},
+    Error, Result,
+};
+
+/// A parameter of a formatted message, written in binary form after the format string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    /// Written by `%d`, `%i` or `%c`
+    Int(i64),
+    /// Written by `%u`
+    UInt(u64),
+    /// Written by `%x`
+    Hex(u64),
+    /// Written by `%f` (or any other floating point specifier)
+    Float(f64),
+    /// Written by `%s`, as a length followed by the bytes of the string
+    String(String),
+    /// Written by `%-s`, as a tokenized string, so repeated strings are only written once
+    Token(String),
+    /// Written by `%p`, in the encoder's pointer size
+    Pointer(u64),
+}
+
+/// Writes entries in the LiteCore binary log format, which `Decoder` reads.
+/// Domains, format strings and tokenized string parameters are interned as tokens, and objects are interned in the
+/// object table, so each is only written in full the first time it is used.
+pub struct Encoder<W>
+where
+    W: Write,
+{
+    writer: W,
+    pointer_size: u8,
+    start_time: NaiveDateTime,
+    elapsed_ticks: u64,
+    tokens: HashMap<String, u64>,
+    objects: HashMap<String, u64>,
+}
+
+impl Encoder<BufWriter<std::fs::File>> {
+    /// Create a binary log file at the given path, replacing any existing file.
+    pub fn create(
+        path: impl AsRef<Path>,
+        start_time: NaiveDateTime,
+        pointer_size: u8,
+    ) -> Result<Self> {
+        Self::new(
+            BufWriter::new(std::fs::File::create(path)?),
+            start_time,
+            pointer_size,
+        )
+    }
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    /// Write the header of the binary logs. Entry timestamps are written relative to `start_time`, which is stored
+    /// with a precision of seconds. `pointer_size` must be 4 or 8.
+    pub fn new(mut writer: W, start_time: NaiveDateTime, pointer_size: u8) -> Result<Self> {
+        if pointer_size != 4 && pointer_size != 8 {
+            return Err(Error::CannotEncode(format!(
+                "Invalid pointer size {}",
+                pointer_size
+            )));
+        }
+        let seconds = u64::try_from(start_time.and_utc().timestamp()).map_err(|_| {
+            Error::CannotEncode(format!("Start time {} is before the epoch", start_time))
+        })?;
+
+        writer.write_all(&MAGIC_NUMBER)?;
+        writer.write_all(&[FORMAT_VERSION, pointer_size])?;
+        varint::write(&mut writer, seconds)?;
+
+        Ok(Self {
+            writer,
+            pointer_size,
+            // Truncated to seconds, as that is what the decoder will read.
+            start_time: chrono::DateTime::from_timestamp(seconds as i64, 0)
+                .unwrap()
+                .naive_utc(),
+            elapsed_ticks: 0,
+            tokens: HashMap::new(),
+            objects: HashMap::new(),
+        })
+    }
+
+    /// Write the key/value header entry, i.e. `serialNo`, `logDirectory`, which is read by `Decoder::metadata`.
+    /// This should be the first entry.
+    pub fn write_metadata(&mut self, metadata: &[(String, String)]) -> Result<()> {
+        let fields: Vec<String> = metadata
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let message = format!("---- {} ----", fields.join(","));
+        self.write_entry(&Entry {
+            timestamp: self.timestamp(),
+            level: Level::Info,
+            domain: String::new(),
+            object: None,
+            message,
+        })
+    }
+
+    /// Write an entry. The message is written as a single string parameter.
+    pub fn write_entry(&mut self, entry: &Entry) -> Result<()> {
+        self.write_formatted(
+            entry.timestamp,
+            entry.level,
+            &entry.domain,
+            entry.object.as_deref(),
+            "%s",
+            &[Arg::String(entry.message.clone())],
+        )
+    }
+
+    /// Write an entry with a printf-style format string, followed by its parameters. The parameters must match the
+    /// specifiers in `format`, as the format string is not checked.
+    /// Entries must be written in chronological order, no earlier than the start time.
+    pub fn write_formatted(
+        &mut self,
+        timestamp: NaiveDateTime,
+        level: Level,
+        domain: &str,
+        object: Option<&str>,
+        format: &str,
+        args: &[Arg],
+    ) -> Result<()> {
+        // Check everything before writing, so an invalid entry isn't partially written.
+        let strings = [domain, format].into_iter().chain(object);
+        for string in strings.chain(args.iter().filter_map(|arg| match arg {
+            Arg::Token(string) => Some(string.as_str()),
+            _ => None,
+        })) {
+            if string.as_bytes().contains(&0) {
+                return Err(Error::CannotEncode(format!(
+                    "String '{}' contains a NULL character",
+                    string
+                )));
+            }
+        }
+        for arg in args {
+            match arg {
+                Arg::Int(i64::MIN) => {
+                    return Err(Error::CannotEncode(format!(
+                        "Integer parameter {} is out of range",
+                        i64::MIN
+                    )))
+                }
+                Arg::Pointer(value) if self.pointer_size == 4 && u32::try_from(*value).is_err() => {
+                    return Err(Error::CannotEncode(format!(
+                        "Pointer {:#x} is larger than 4 bytes",
+                        value
+                    )))
+                }
+                _ => (),
+            }
+        }
+
+        let ticks = (timestamp - self.start_time)
+            .num_microseconds()
+            .and_then(|ticks| u64::try_from(ticks).ok())
+            .filter(|ticks| *ticks >= self.elapsed_ticks)
+            .ok_or_else(|| {
+                Error::CannotEncode(format!(
+                    "Timestamp {} is before the previous entry",
+                    timestamp
+                ))
+            })?;
+        varint::write(&mut self.writer, ticks - self.elapsed_ticks)?;
+        self.elapsed_ticks = ticks;
+
+        let level = LEVELS.iter().position(|l| *l == level).unwrap() as u8;
+        self.writer.write_all(&[level])?;
+
+        self.write_token(domain)?;
+        self.write_object(object)?;
+        self.write_token(format)?;
+
+        for arg in args {
+            self.write_arg(arg)?;
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered output and return the writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// The timestamp of the latest entry.
+    fn timestamp(&self) -> NaiveDateTime {
+        self.start_time + chrono::TimeDelta::microseconds(self.elapsed_ticks as i64)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_arg(&mut self, arg: &Arg) -> Result<()> {
+        match arg {
+            Arg::Int(value) => {
+                self.writer.write_all(&[u8::from(*value < 0)])?;
+                varint::write(&mut self.writer, value.unsigned_abs())?;
+            }
+            Arg::UInt(value) | Arg::Hex(value) => {
+                varint::write(&mut self.writer, *value)?;
+            }
+            Arg::Float(value) => self.writer.write_all(&value.to_le_bytes())?,
+            Arg::String(string) => {
+                varint::write(&mut self.writer, string.len() as u64)?;
+                self.writer.write_all(string.as_bytes())?;
+            }
+            Arg::Token(string) => self.write_token(string)?,
+            Arg::Pointer(value) if self.pointer_size == 4 => {
+                self.writer.write_all(&(*value as u32).to_le_bytes())?;
+            }
+            Arg::Pointer(value) => self.writer.write_all(&value.to_le_bytes())?,
+        }
+        Ok(())
+    }
+
+    /// Write the ID of the token, followed by the string if this is the first use of the token.
+    fn write_token(&mut self, string: &str) -> Result<()> {
+        if let Some(id) = self.tokens.get(string) {
+            varint::write(&mut self.writer, *id)?;
+        } else {
+            let id = self.tokens.len() as u64;
+            self.tokens.insert(string.to_string(), id);
+            varint::write(&mut self.writer, id)?;
+            self.write_string(string)?;
+        }
+        Ok(())
+    }
+
+    /// Write the ID of the object (0 for none), followed by the object path if this is the first use of the object.
+    fn write_object(&mut self, object: Option<&str>) -> Result<()> {
+        let Some(object) = object else {
+            varint::write(&mut self.writer, 0)?;
+            return Ok(());
+        };
+        if let Some(id) = self.objects.get(object) {
+            varint::write(&mut self.writer, *id)?;
+        } else {
+            let id = self.objects.len() as u64 + 1;
+            self.objects.insert(object.to_string(), id);
+            varint::write(&mut self.writer, id)?;
+            self.write_string(object)?;
+        }
+        Ok(())
+    }
+
+    /// Write a null-terminated string.
+    fn write_string(&mut self, string: &str) -> Result<()> {
+        self.writer.write_all(string.as_bytes())?;
+        self.writer.write_all(&[0])?;
+        Ok(())
+    }
+}
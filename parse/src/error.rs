@@ -20,6 +20,10 @@ pub enum Error {
     SerdeYaml(#[from] serde_yaml::Error),
     #[error("Parse Int Error {0}")]
     ParseInt(#[from] std::num::ParseIntError),
+    #[error("Zip Error {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Glob Error {0}")]
+    Glob(#[from] globset::Error),
     #[error("No valid logs at path \"{0}\"")]
     NotLogs(PathBuf),
     #[error("Unsupported CBL Version {0}")]
@@ -40,12 +44,55 @@ pub enum Error {
     NoObject,
     #[error("Invalid binary logs: '{0}' at {1}")]
     InvalidBinaryLogs(String, u64),
+    #[error("Skipped corrupt binary logs ({0}) from {1}, {2} bytes")]
+    CorruptBinaryLogs(String, u64, u64),
+    #[error("Cannot encode binary logs: {0}")]
+    CannotEncode(String),
     #[error("Invalid varint in binary logs")]
     InvalidVarint,
     #[error("No log level in line")]
     NoLevel,
     #[error("Cannot parse: {0}")]
     CannotParse(String),
+    #[error("Database schema version {0} is newer than this version of lumberjack supports ({1})")]
+    SchemaTooNew(u32, u32),
+    #[error("Parser output was disconnected")]
+    Disconnected,
+}
+
+impl Error {
+    /// The name of the error variant, i.e. "NoTimestamp".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Sqlite(_) => "Sqlite",
+            Error::Io(_) => "Io",
+            Error::Regex(_) => "Regex",
+            Error::Semver(_) => "Semver",
+            Error::ChronoParse(_) => "ChronoParse",
+            Error::Boxed(_) => "Boxed",
+            Error::SerdeYaml(_) => "SerdeYaml",
+            Error::ParseInt(_) => "ParseInt",
+            Error::Zip(_) => "Zip",
+            Error::Glob(_) => "Glob",
+            Error::NotLogs(_) => "NotLogs",
+            Error::UnsupportedVersion(_) => "UnsupportedVersion",
+            Error::UnsupportedPlatform(_) => "UnsupportedPlatform",
+            Error::NoSuchLevel(_) => "NoSuchLevel",
+            Error::UnknownEvent => "UnknownEvent",
+            Error::IgnoredEvent => "IgnoredEvent",
+            Error::NoTimestamp => "NoTimestamp",
+            Error::NoDomain => "NoDomain",
+            Error::NoObject => "NoObject",
+            Error::InvalidBinaryLogs(_, _) => "InvalidBinaryLogs",
+            Error::CorruptBinaryLogs(_, _, _) => "CorruptBinaryLogs",
+            Error::CannotEncode(_) => "CannotEncode",
+            Error::InvalidVarint => "InvalidVarint",
+            Error::NoLevel => "NoLevel",
+            Error::CannotParse(_) => "CannotParse",
+            Error::SchemaTooNew(_, _) => "SchemaTooNew",
+            Error::Disconnected => "Disconnected",
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,54 +1,309 @@
+mod archive;
 pub mod data;
-pub(crate) mod decoder;
+pub mod decoder;
+pub mod encoder;
 mod error;
 mod parser;
+mod report;
+mod source;
 pub mod util;
 
-use crate::data::open_db;
-use crate::data::{EventType, Insertable};
-use crate::parser::Parser;
+use crate::data::{delete_files, insert_missing_event_types, merge_db, open_db, sequence_lines};
+use crate::data::{EventType, FromRow, Insertable};
+use crate::parser::{Parser, ParserOutput};
 pub use error::{Error, Result};
-use std::path::Path;
+use rusqlite::OptionalExtension;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 pub use crate::parser::Options;
+pub use crate::report::{FailedFile, FileReport, ParseReport, SkippedRegion};
 
 /// Parse logs from the given `in_path` into a SQLite database at the given `out_path`.
-/// Return the number of lines which failed to parse.
-pub fn parse(in_path: &Path, out_path: &Path, options: Options) -> Result<u64> {
+/// Return a `ParseReport` summarising each file which was parsed.
+pub fn parse(in_path: &Path, out_path: &Path, options: Options) -> Result<ParseReport> {
     log::info!("Parsing logs at {:?}", in_path);
 
-    let mut conn = open_db(out_path, true)?;
+    let start_time = Instant::now();
 
     let parser = Parser::new(in_path, options)?;
 
-    let mut total_files = 0_u64;
-    let mut total_lines = 0_u64;
+    let conn = create_db(out_path)?;
+
+    write_db(parser, conn, out_path, start_time)
+}
+
+/// Update a database written by a previous `parse` of logs from `in_path`, i.e. after more logs were added to a bundle.
+/// Files which haven't changed since are skipped, changed files are parsed again, replacing their rows, and new files
+/// are added. The rows of files which are no longer found are removed, and event types added since the database was
+/// written are inserted. If there's no database at `out_path`, it's created as by `parse`.
+/// Return a `ParseReport` summarising each file which was parsed, and listing those which were unchanged.
+pub fn update(in_path: &Path, out_path: &Path, options: Options) -> Result<ParseReport> {
+    if !out_path.exists() {
+        return parse(in_path, out_path, options);
+    }
+
+    log::info!("Updating {:?} with logs at {:?}", out_path, in_path);
+
+    let start_time = Instant::now();
+
+    let mut conn = open_db(out_path, false)?;
+    insert_missing_event_types(&mut conn)?;
+    let existing = conn
+        .prepare("SELECT * FROM files")?
+        .query_map([], data::File::from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let first_session_id: u32 =
+        conn.query_row("SELECT COALESCE(MAX(id) + 1, 0) FROM sessions", [], |row| {
+            row.get(0)
+        })?;
+
+    let mut parser = Parser::new(in_path, options)?;
+    let stale = parser.plan_update(existing, first_session_id)?;
+    delete_files(&mut conn, &stale)?;
+
+    write_db(parser, conn, out_path, start_time)
+}
+
+/// Parse logs read from `reader` into a SQLite database at the given `out_path`, i.e. logs piped from stdin.
+/// The logs may be text or binary, or an archive of logs. Text logs are parsed as they're read, while binary logs and
+/// archives, which are read more than once, are copied to a temporary file first.
+/// `label` is stored as the path of the file. If it's named like a CBL log file (i.e. "cbl_info_1722271812929.cbllog")
+/// the timestamp and level of the file are taken from it, as they would be for a file.
+/// Only the start of text logs is searched for a version line, and there are no other files to take the patterns from,
+/// so logs whose version line comes later need `Options::cbl_version` and `Options::platform`.
+/// Return a `ParseReport` summarising each file which was parsed.
+pub fn parse_reader(
+    reader: impl BufRead + Send + 'static,
+    label: &str,
+    out_path: &Path,
+    options: Options,
+) -> Result<ParseReport> {
+    log::info!("Parsing logs from '{}'", label);
+
+    let start_time = Instant::now();
+
+    let parser = Parser::from_reader(reader, label, options)?;
+
+    let conn = create_db(out_path)?;
+
+    write_db(parser, conn, out_path, start_time)
+}
+
+/// A database written by `parse` to be merged with others, and the name of the device its logs are from.
+#[derive(Debug, Clone)]
+pub struct MergeInput {
+    pub path: PathBuf,
+    pub device: String,
+}
+
+impl MergeInput {
+    /// A database whose device is named after its file, i.e. "pixel-7" for "pixel-7.sqlite".
+    pub fn new(path: PathBuf) -> Self {
+        let device = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self { path, device }
+    }
+}
+
+/// Merge several databases written by `parse` into a new SQLite database at `out_path`, so logs parsed separately
+/// (i.e. from each device) can be queried together. File and session IDs are renumbered to be unique across the
+/// inputs, event types are matched by name, and each file is tagged with the database and device it came from (see
+/// `File::database` and `File::device`). Lines are sequenced across all the inputs.
+pub fn merge(inputs: &[MergeInput], out_path: &Path) -> Result<()> {
+    // The output is replaced, so it mustn't be one of the inputs.
+    if let Ok(out_path) = out_path.canonicalize() {
+        if inputs
+            .iter()
+            .any(|input| input.path.canonicalize().is_ok_and(|path| path == out_path))
+        {
+            return Err(Error::CannotParse(format!(
+                "Can't merge {:?} into itself",
+                out_path
+            )));
+        }
+    }
 
-    {
-        let mut tx = conn.transaction()?;
-        enum_iterator::all::<EventType>().db_insert(&mut tx)?;
-        tx.commit()?;
+    let mut conn = create_db(out_path)?;
+    for input in inputs {
+        log::info!("Merging {:?} from device '{}'", input.path, input.device);
+        merge_db(
+            &mut conn,
+            &input.path,
+            &input.path.to_string_lossy(),
+            &input.device,
+        )?;
     }
 
-    let mut error_count = 0_u64;
+    sequence_lines(&conn)?;
 
-    for result in parser.parse() {
-        error_count += result.error_count;
-        total_files += 1;
-        total_lines += result.lines.len() as u64;
-        let mut tx = conn.transaction()?;
-        result.file.db_insert(&mut tx)?;
-        result.lines.into_iter().db_insert(&mut tx)?;
-        tx.commit()?;
+    log::info!("Wrote merged data to {:?}", out_path);
+    Ok(())
+}
+
+/// Write the output of `parser` to the SQLite database at `out_path`.
+fn write_db(
+    parser: Parser,
+    mut conn: rusqlite::Connection,
+    out_path: &Path,
+    start_time: Instant,
+) -> Result<ParseReport> {
+    let mut report = ParseReport::default();
+
+    // Lines are inserted chunk by chunk as they are parsed, so the whole file is never held in memory.
+    for output in parser.parse() {
+        insert_output(&mut conn, output, &mut report)?;
     }
 
+    // Lines can only be put in order across files once every file has been parsed.
+    sequence_lines(&conn)?;
+
     log::info!(
         "Parsing complete. Parsed {} files, {} lines",
-        total_files,
-        total_lines,
+        report.files.len(),
+        report.parsed_count(),
     );
 
     log::info!("Wrote parsed data to {:?}", out_path);
 
-    Ok(error_count)
+    report.duration = start_time.elapsed();
+
+    Ok(report)
+}
+
+/// Create a new SQLite database at `out_path`, replacing any existing database.
+fn create_db(out_path: &Path) -> Result<rusqlite::Connection> {
+    let mut conn = open_db(out_path, true)?;
+    let mut tx = conn.transaction()?;
+    enum_iterator::all::<EventType>().db_insert(&mut tx)?;
+    tx.commit()?;
+    Ok(conn)
+}
+
+/// Insert one output of the parser into the database, or add it to `report`.
+fn insert_output(
+    conn: &mut rusqlite::Connection,
+    output: ParserOutput,
+    report: &mut ParseReport,
+) -> Result<()> {
+    let mut tx = conn.transaction()?;
+    match output {
+        ParserOutput::File(file) => {
+            file.db_insert(&mut tx)?;
+        }
+        ParserOutput::Session(session) => {
+            session.db_insert(&mut tx)?;
+        }
+        ParserOutput::Metadata(metadata) => {
+            metadata.into_iter().db_insert(&mut tx)?;
+        }
+        ParserOutput::Lines(lines) => {
+            lines.into_iter().db_insert(&mut tx)?;
+        }
+        ParserOutput::Errors(errors) => {
+            errors.into_iter().db_insert(&mut tx)?;
+        }
+        ParserOutput::FileComplete(file_report) => {
+            tx.execute(
+                "UPDATE files SET line_count = $1, hash = $2 WHERE id = $3",
+                rusqlite::params![
+                    file_report.line_count,
+                    file_report.hash,
+                    file_report.file_id
+                ],
+            )?;
+            report.files.push(file_report);
+        }
+        ParserOutput::FileUnchanged(path) => {
+            report.unchanged_files.push(path);
+        }
+        ParserOutput::FileFailed(failed) => {
+            report.failed_files.push(failed);
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Parses logs into a SQLite database while they're still being written, i.e. during a repro session.
+/// Each `poll` appends the lines written since the previous poll, including those in any new log files, so nothing is
+/// parsed twice. Text and binary logs are both followed.
+pub struct Follower {
+    follower: parser::Follower,
+    conn: rusqlite::Connection,
+}
+
+impl Follower {
+    /// Create a new SQLite database at `out_path` for the logs at `in_path`, which are parsed by the first `poll`.
+    pub fn new(in_path: &Path, out_path: &Path, options: Options) -> Result<Self> {
+        let follower = parser::Follower::new(in_path, options)?;
+        let conn = create_db(out_path)?;
+        // Release the lock between polls, so the database can be queried while the logs are followed.
+        conn.execute_batch("PRAGMA locking_mode=NORMAL;")?;
+        Ok(Self { follower, conn })
+    }
+
+    /// Parse any lines written since the last poll, and any new log files. Returns a report of the files which failed
+    /// to parse, which are no longer followed, and the number of lines added to the database.
+    /// Lines which were added by an earlier poll keep their `seq`, unless new lines from another file have an earlier
+    /// timestamp, in which case the lines after those are numbered again.
+    pub fn poll(&mut self) -> Result<(ParseReport, u64)> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(parser::CHANNEL_BOUND);
+        let mut report = ParseReport::default();
+        let mut line_count = 0_u64;
+
+        let follower = &mut self.follower;
+        let conn = &mut self.conn;
+        std::thread::scope(|scope| {
+            let handle = scope.spawn(move || follower.poll(&sender));
+            for output in receiver {
+                if let ParserOutput::Lines(lines) = &output {
+                    line_count += lines.len() as u64;
+                }
+                insert_output(conn, output, &mut report)?;
+            }
+            handle.join().expect("Follower thread panicked")
+        })?;
+
+        // New lines may come before lines from other files which were already sequenced.
+        sequence_lines(&self.conn)?;
+        Ok((report, line_count))
+    }
+}
+
+/// Read the lines surrounding a parsed line from its original log file.
+/// Returns up to `context` lines either side of `line_num` in the file with the given `file_id`, including lines which
+/// were not parsed, as pairs of line_num and the raw text of the line.
+/// Logs which were parsed with `parse_reader` can't be read again, so this fails for those files.
+pub fn line_context(
+    conn: &rusqlite::Connection,
+    file_id: u32,
+    line_num: u32,
+    context: u32,
+) -> Result<Vec<(u32, String)>> {
+    let (path, archive_path, first_line_num): (String, Option<String>, u32) = conn.query_row(
+        "SELECT path, archive_path, first_line_num FROM files WHERE id = $1",
+        rusqlite::params![file_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let source = source::LogSource::from_parts(path.into(), archive_path.as_deref());
+
+    // Start reading from the nearest parsed line before the context, to avoid reading the whole file.
+    let start: (u32, u64) = conn
+        .query_row(
+            "
+            SELECT line_num, source_offset FROM lines
+            WHERE file_id = $1 AND line_num <= $2
+            ORDER BY line_num DESC
+            LIMIT 1",
+            rusqlite::params![file_id, line_num.saturating_sub(context)],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .unwrap_or((first_line_num, 0));
+
+    parser::read_context(&source, first_line_num, start, line_num, context)
 }
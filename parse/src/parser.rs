@@ -1,101 +1,351 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, BufReader, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::{mpsc::SyncSender, Arc, Mutex},
+    time::Instant,
 };
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeDelta};
 use lazy_static::lazy_static;
 use rayon::{iter::Either, prelude::*};
 use regex::Regex;
 use regex_patterns::{LevelNames, Patterns};
 
 use crate::{
-    data::{parse_event, File, Level, Line},
-    decoder, Error, Result,
+    data::{
+        parse_event, Event, EventType, File, Level, Line, Metadata, ParseError, PatternsSource,
+        Session,
+    },
+    decoder::{self, Entry},
+    report::{FailedFile, FileReport, SkippedRegion},
+    source::{self, FileFilter, HashingReader, LogSource, ReadSeek},
+    Error, Result,
 };
 
+/// The number of lines which are read from a file and parsed in parallel at a time.
+const CHUNK_SIZE: usize = 16_384;
+/// The number of lines at the start of a file which are searched for a binary log header.
+const HEADER_SEARCH_LINES: usize = 8;
+/// The number of lines at the start of a file which are searched for a version line. A file without one in these lines
+/// is parsed with the patterns of another file, so a large file which isn't logs isn't read to the end.
+pub(crate) const VERSION_SEARCH_LINES: usize = 10_000;
+/// The number of lines at the start of a file with no version line which are checked for timestamps, before parsing it
+/// with the patterns of another file.
+const SIBLING_CHECK_LINES: usize = 16;
+/// The number of parsed chunks which can be waiting to be consumed before the parser blocks.
+/// Together with `CHUNK_SIZE` this bounds the memory used by the parser, regardless of file size.
+pub(crate) const CHANNEL_BOUND: usize = 4;
+
 pub struct Parser {
-    files: Vec<PathBuf>,
-    patterns: regex_patterns::Patterns,
-    version: semver::Version,
+    files: Vec<LogFile>,
+    /// The directory which was parsed, or the directory containing the file which was parsed. Paths in the `files`
+    /// table are stored relative to this.
+    root: PathBuf,
     options: Options,
+    /// The ID of the first session found. Sessions from a previous run are kept when updating a database.
+    first_session_id: u32,
+    patterns: PatternsCache,
 }
 
-pub struct ParserOutput {
-    pub file: File,
-    pub lines: Vec<Line>,
-    pub error_count: u64,
+/// Compiled patterns by CBL version and platform name. Compiling patterns compiles every regex for the version, so
+/// they're compiled once and shared by every file and session with the same version and platform.
+#[derive(Default)]
+struct PatternsCache(Mutex<HashMap<(semver::Version, String), Patterns>>);
+
+impl PatternsCache {
+    fn get(&self, version: &semver::Version, platform: &str) -> Result<Patterns> {
+        let mut cache = self.0.lock().unwrap();
+        let key = (version.clone(), platform.to_string());
+        if let Some(patterns) = cache.get(&key) {
+            return Ok(patterns.clone());
+        }
+        let patterns = regex_patterns::patterns_for_platform(version, platform)?;
+        cache.insert(key, patterns.clone());
+        Ok(patterns)
+    }
 }
 
-#[derive(Default, Clone, Copy)]
+/// A log file to be parsed, along with the patterns detected for its version and platform.
+struct LogFile {
+    /// The ID of the `File`.
+    id: u32,
+    /// The line_num following the last line of the file, if it was parsed into the database by a previous run and
+    /// hasn't changed since, so it's skipped.
+    parsed_end: Option<u32>,
+    source: LogSource,
+    patterns: Patterns,
+    version: semver::Version,
+    /// The level of the lines in the file, from the file name.
+    level: Option<Level>,
+    /// The index of the previous file of the same level, which this file continues from after rotation.
+    predecessor: Option<usize>,
+    patterns_source: PatternsSource,
+}
+
+/// A session (app launch) within a log file, which starts at a version line.
+struct ActiveSession {
+    /// The ID of the `Session`, or `None` for any lines before the first version line.
+    id: Option<u32>,
+    patterns: Patterns,
+    version: semver::Version,
+}
+
+/// The progress of parsing a file, which is carried between chunks, and between polls when following a file.
+struct FileState {
+    file: File,
+    lines: LineReader,
+    /// Hashes the file as `lines` reads it.
+    content: HashingReader,
+    /// The session of the last line read.
+    session: Arc<ActiveSession>,
+    /// The line_num of the next line to be read.
+    next_line_num: u32,
+    /// The days added to partial timestamps since the date rolled over.
+    additional_days: TimeDelta,
+    line_count: usize,
+    ok_count: usize,
+    unknown_count: usize,
+    ignored_err_count: usize,
+    error_kinds: BTreeMap<&'static str, u64>,
+    skipped_regions: Vec<SkippedRegion>,
+    #[cfg(debug_assertions)]
+    reduced_errors: HashMap<String, (String, usize)>,
+    start_time: Instant,
+}
+
+/// Output is streamed from the parser file by file, chunk by chunk.
+/// For each file there is a `File`, followed by any number of `Lines`, followed by `FileComplete`.
+/// If the file can't be parsed, `FileFailed` is sent instead of `FileComplete`.
+pub enum ParserOutput {
+    /// Parsing of a new file has started.
+    File(File),
+    /// A version line in the current file, which starts a new session.
+    Session(Session),
+    /// Key/value metadata from the header of the current file.
+    Metadata(Vec<Metadata>),
+    /// A chunk of lines parsed from the current file.
+    Lines(Vec<Line>),
+    /// The lines from the current chunk which failed to parse.
+    Errors(Vec<ParseError>),
+    /// The current file has been fully parsed.
+    FileComplete(FileReport),
+    /// A file hasn't changed since it was parsed into the database being updated, so it was skipped.
+    FileUnchanged(String),
+    /// Parsing of a file stopped due to an error.
+    FileFailed(FailedFile),
+}
+
+#[derive(Default, Clone)]
 pub struct Options {
     /// Reduce and coalesce similar log lines in trace output. Useful when dealing with a large number of parsing errors.
     /// Ignored in release builds.
     pub reduce_lines: bool,
+    /// Don't store CBL lines which don't match any known event. By default they are stored with `EventType::Unknown`.
+    pub skip_unknown: bool,
+    /// Skip corrupt regions of binary logs, continuing from the next valid entry. By default a corrupt binary log stops
+    /// parsing of that file.
+    pub recover: bool,
+    /// Parse every file with the patterns for this CBL version, instead of the version detected from each file.
+    /// Must be given together with `platform`.
+    pub cbl_version: Option<semver::Version>,
+    /// The name of the platform patterns to use with `cbl_version` (see `parse/src/patterns`), i.e. "vanilla".
+    pub platform: Option<String>,
+    /// Only parse files whose path relative to the parsed directory matches one of these globs, i.e. "**/cbl_info_*".
+    /// Files within archives are matched by the path of the archive followed by "!/" and the path within the archive.
+    /// By default every file is parsed.
+    pub include: Vec<String>,
+    /// Don't parse files, or search directories, whose relative path matches one of these globs.
+    pub exclude: Vec<String>,
+    /// How many levels of subdirectories to search for logs. `Some(0)` only searches the given directory. By default
+    /// there is no limit.
+    pub max_depth: Option<usize>,
 }
 
 impl Parser {
     pub fn new(path: &Path, options: Options) -> Result<Self> {
-        let files = Self::find_log_files(path)?;
+        log::debug!(
+            "Searching for valid log files in file or directory {:?}",
+            path
+        );
+        let filter = FileFilter::new(&options.include, &options.exclude, options.max_depth)?;
+        // Archives are searched for log files, which are read straight from the archive without extracting them.
+        // Sorted so file IDs, and the order of lines with the same timestamp, don't depend on the file system.
+        let sources = source::find_sources(path, &filter)?;
+        let root = source::root_dir(path).to_path_buf();
+        Self::with_sources(sources, path, root, options)
+    }
+
+    /// Parse logs read from `reader`, in text or binary format, or an archive of logs. Text logs are parsed as they're
+    /// read, while binary logs and archives are copied to a temporary file first. `label` is used in place of the file
+    /// name, so the timestamp and level are read from it if it's named like a CBL log file, i.e.
+    /// "cbl_info_1722271812929.cbllog". Only the first `VERSION_SEARCH_LINES` lines of text logs are searched for a
+    /// version line, so logs whose version line comes later need `Options::cbl_version` and `Options::platform`.
+    pub fn from_reader(
+        reader: impl BufRead + Send + 'static,
+        label: &str,
+        options: Options,
+    ) -> Result<Self> {
+        log::debug!("Reading logs '{}'", label);
+        let filter = FileFilter::new(&options.include, &options.exclude, options.max_depth)?;
+        let sources = source::expand_stream(LogSource::stream(label, reader)?, &filter)?;
+        Self::with_sources(sources, Path::new(label), PathBuf::new(), options).inspect_err(|err| {
+            // Unlike a file, the stream has no siblings to take patterns from.
+            if matches!(err, Error::NotLogs(_)) {
+                log::error!(
+                    "No version line was found in the first {} lines of '{}'. Logs whose version line comes later need \
+                     the CBL version and platform to be given",
+                    VERSION_SEARCH_LINES,
+                    label
+                );
+            }
+        })
+    }
+
+    fn with_sources(
+        sources: Vec<LogSource>,
+        path: &Path,
+        root: PathBuf,
+        options: Options,
+    ) -> Result<Self> {
+        let patterns = PatternsCache::default();
+        let mut files = Self::log_files(sources, &[], &options, &patterns)?;
         if files.is_empty() {
             log::error!("No valid log files found at path {:?}!", path);
             return Err(Error::NotLogs(path.to_path_buf()));
         }
-        let (patterns, version) = regex_patterns::patterns_for_file(&files[0])?;
+        link_rotated_files(&mut files);
+        for (index, file) in files.iter_mut().enumerate() {
+            file.id = index as u32;
+        }
         Ok(Self {
             files,
-            patterns,
-            version,
+            root,
             options,
+            first_session_id: 0,
+            patterns,
         })
     }
 
-    pub fn parse(&self) -> impl Iterator<Item = ParserOutput> + '_ {
-        ParserIter {
-            parser: self,
-            index: 0,
-        }
+    /// Parse all files on a background thread, returning an iterator over the output.
+    /// The parser will only run ahead of the consumer by `CHANNEL_BOUND` chunks.
+    pub fn parse(self) -> impl Iterator<Item = ParserOutput> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(CHANNEL_BOUND);
+
+        std::thread::spawn(move || {
+            let mut next_session_id = self.first_session_id;
+            // The line_num following the last line of each file, which the next file of the same level continues from.
+            let mut end_line_nums = vec![0_u32; self.files.len()];
+            for index in 0..self.files.len() {
+                if let Some(parsed_end) = self.files[index].parsed_end {
+                    end_line_nums[index] = parsed_end;
+                    let path = self.files[index].source.to_string();
+                    log::debug!("Skipping '{}', which hasn't changed", path);
+                    if sender.send(ParserOutput::FileUnchanged(path)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                let mut next_line_num = self.files[index]
+                    .predecessor
+                    .map_or(0, |predecessor| end_line_nums[predecessor]);
+                let result =
+                    self.parse_file(index, &mut next_line_num, &mut next_session_id, &sender);
+                end_line_nums[index] = next_line_num;
+                match result {
+                    Ok(()) => (),
+                    Err(Error::Disconnected) => break,
+                    Err(err) => {
+                        let path = self.files[index].source.to_string();
+                        log::error!("Error parsing file '{}': {}", path, err);
+                        let failed = FailedFile {
+                            path,
+                            error: err.to_string(),
+                        };
+                        if sender.send(ParserOutput::FileFailed(failed)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        receiver.into_iter()
     }
 
-    fn parse_file(&self, index: usize) -> Result<ParserOutput> {
-        let path = self.files[index].as_path();
-        let lines = read_lines(path)?;
-        let file_name = path
-            .file_stem()
-            .expect("Path is not a file!")
-            .to_str()
-            .expect("Path contains invalid Unicode!");
+    /// Parse the file at `index`, numbering its lines from `next_line_num`, which is advanced past each line read.
+    fn parse_file(
+        &self,
+        index: usize,
+        next_line_num: &mut u32,
+        next_session_id: &mut u32,
+        sender: &SyncSender<ParserOutput>,
+    ) -> Result<()> {
+        let mut state = self.start_file(index, *next_line_num, false, sender)?;
+        let result = self.parse_lines(index, &mut state, next_session_id, sender);
+        *next_line_num = state.next_line_num;
+        result?;
+        state.file.hash = state.content.finish()?;
+        sender
+            .send(ParserOutput::FileComplete(self.file_report(index, state)))
+            .map_err(|_| Error::Disconnected)
+    }
 
-        let timestamp = timestamp_from_filename(file_name);
+    /// Start parsing the file at `index`, sending its `File`. Its lines are numbered from `first_line_num`.
+    /// If `follow` is set, lines which are still being written at the end of the file aren't read until they're
+    /// complete, so the file can continue to be read as it grows.
+    fn start_file(
+        &self,
+        index: usize,
+        first_line_num: u32,
+        follow: bool,
+        sender: &SyncSender<ParserOutput>,
+    ) -> Result<FileState> {
+        let start_time = Instant::now();
+        let log_file = &self.files[index];
+        let source = &log_file.source;
+        let patterns = &log_file.patterns;
+
+        let timestamp = timestamp_from_filename(source.file_stem());
 
         let timestamp = if let Some(timestamp) = timestamp {
             // Timestamp from filename
             Ok(timestamp)
-        } else if self.patterns.platform.full_timestamp {
+        } else if patterns.platform.full_timestamp {
             // Timestamp from first valid full timestamp in log file
-            let mut i = 0_usize;
-            loop {
+            let mut timestamp = None;
+            for line in read_lines(source)? {
+                let line = line?;
+                if let Some(entry) = &line.entry {
+                    timestamp = Some(entry.timestamp);
+                    break;
+                }
                 match parse_timestamp(
-                    &lines[i],
-                    &self.patterns.platform.timestamp,
-                    self.patterns.platform.full_timestamp,
-                    &self.patterns.platform.timestamp_formats,
+                    &line.text,
+                    &patterns.platform.timestamp,
+                    patterns.platform.full_timestamp,
+                    &patterns.platform.timestamp_formats,
                 ) {
-                    Ok(Timestamp::Full(ts)) => break Ok(ts),
-                    Ok(Timestamp::Partial(_)) => unreachable!(),
-                    Err(_) => {
-                        i += 1;
-                        if i >= lines.len() {
-                            return Err(Error::CannotParse(format!(
-                                "Could not find a full timestamp in file {:?}!",
-                                path
-                            )));
-                        }
+                    Ok(Timestamp::Full(ts)) => {
+                        timestamp = Some(ts);
+                        break;
                     }
+                    Ok(Timestamp::Partial(_)) => unreachable!(),
+                    Err(_) => continue,
                 }
             }
+            timestamp.ok_or_else(|| {
+                Error::CannotParse(format!(
+                    "Could not find a full timestamp in file '{}'!",
+                    source
+                ))
+            })
+        } else if source.is_stream() {
+            // Logs read from a stream have no creation time, so use the time they were read
+            Ok(chrono::Utc::now().naive_utc())
         } else {
-            // Timestamp from file creation time
+            // Timestamp from file creation time, or the creation time of the archive containing the file
+            let path = source.path.as_path();
             let meta = std::fs::metadata(path)?;
             let created_seconds = meta
                 .created()?
@@ -112,14 +362,75 @@ impl Parser {
                 })
         }?;
 
-        let line_count = lines.len();
-
+        let (size, modified) = source.disk_metadata()?;
         let file = File {
-            id: index as u32,
-            path: path.to_string_lossy().to_string(),
+            id: log_file.id,
+            path: source.path.to_string_lossy().to_string(),
+            relative_path: source
+                .relative_path(&self.root)
+                .to_string_lossy()
+                .to_string(),
+            archive_path: source.archive_path(),
             timestamp,
+            version: log_file.version.to_string(),
+            platform: patterns.platform.name.to_string(),
+            level: log_file.level,
+            predecessor_id: log_file
+                .predecessor
+                .map(|predecessor| self.files[predecessor].id),
+            first_line_num,
+            patterns_source: log_file.patterns_source,
+            size,
+            modified,
+            // Hashed as the file is parsed, so it's only known once the whole file has been.
+            hash: String::new(),
+            line_count: None,
+            database: None,
+            device: None,
         };
 
+        sender
+            .send(ParserOutput::File(file.clone()))
+            .map_err(|_| Error::Disconnected)?;
+
+        let content = HashingReader::new(source.open()?);
+        Ok(FileState {
+            file,
+            lines: open_lines(Box::new(content.clone()), follow)?,
+            content,
+            // Lines before the first version line don't belong to any session, and are parsed with the patterns
+            // detected for the file.
+            session: Arc::new(ActiveSession {
+                id: None,
+                patterns: patterns.clone(),
+                version: log_file.version.clone(),
+            }),
+            next_line_num: first_line_num,
+            additional_days: TimeDelta::days(0),
+            line_count: 0,
+            ok_count: 0,
+            unknown_count: 0,
+            ignored_err_count: 0,
+            error_kinds: BTreeMap::new(),
+            skipped_regions: vec![],
+            #[cfg(debug_assertions)]
+            reduced_errors: HashMap::new(),
+            start_time,
+        })
+    }
+
+    /// Parse the lines which can be read from the file at `index`, chunk by chunk, continuing from `state`.
+    fn parse_lines(
+        &self,
+        index: usize,
+        state: &mut FileState,
+        next_session_id: &mut u32,
+        sender: &SyncSender<ParserOutput>,
+    ) -> Result<()> {
+        let log_file = &self.files[index];
+        let source = &log_file.source;
+        let file = &state.file;
+
         let do_log_line_errors = log::log_enabled!(log::Level::Trace);
 
         // Used for reducing and coalescing lines / errors for debugging and building up formats.
@@ -127,241 +438,838 @@ impl Parser {
         #[cfg(debug_assertions)]
         let do_reduce_line_errors = do_log_line_errors && self.options.reduce_lines;
 
-        let results: Vec<LineResult> =
-            // For full timestamp, we can parse all lines in parallel.
-            lines
-                .into_par_iter()
+        loop {
+            let mut chunk: Vec<RawLine> = Vec::with_capacity(CHUNK_SIZE);
+            // An error which stops the file. The lines read before it are still parsed, so only the rest of the file is
+            // lost.
+            let mut failure = None;
+            for line in state.lines.by_ref() {
+                match line {
+                    Ok(line) => chunk.push(line),
+                    Err(Error::CorruptBinaryLogs(error, offset, length))
+                        if self.options.recover =>
+                    {
+                        log::warn!(
+                            "Skipped {} corrupt bytes at {} in '{}': {}",
+                            length,
+                            offset,
+                            source,
+                            error
+                        );
+                        state.skipped_regions.push(SkippedRegion {
+                            offset,
+                            length,
+                            error,
+                        });
+                    }
+                    Err(err) => {
+                        failure = Some(err);
+                        break;
+                    }
+                }
+                if chunk.len() == CHUNK_SIZE {
+                    break;
+                }
+            }
+            if chunk.is_empty() {
+                return failure.map_or(Ok(()), Err);
+            }
+            let is_first_chunk = state.line_count == 0;
+            let first_line_num = state.next_line_num as usize;
+            state.line_count += chunk.len();
+            state.next_line_num += chunk.len() as u32;
+
+            if is_first_chunk {
+                let metadata: Vec<Metadata> = chunk
+                    .iter()
+                    .take(HEADER_SEARCH_LINES)
+                    .find_map(|line| parse_header(&line.text, file.id))
+                    .unwrap_or_default();
+                if !metadata.is_empty() {
+                    sender
+                        .send(ParserOutput::Metadata(metadata))
+                        .map_err(|_| Error::Disconnected)?;
+                }
+            }
+
+            // A new session starts at every version line. Each session is parsed with the patterns for its own version,
+            // as the app may have been upgraded between launches.
+            let version_lines: Vec<(usize, Result<(semver::Version, &str)>)> = chunk
+                .par_iter()
                 .enumerate()
-                .map(|(i, line)| {
-                    let res = self.parse_line(&line, i as u64, &file, file.timestamp.date());
-
-                    let Ok(line) = res else {
-                        let err = res.unwrap_err();
-                        #[cfg(debug_assertions)]
-                        if do_reduce_line_errors {
-                            let reduced_line = reduce_line(&line, &self.patterns);
-                            return LineResult::Err((err, Some(reduced_line)));
-                        } else if do_log_line_errors {
-                            return LineResult::Err((err, Some(line)))
-                        } else {
-                            return LineResult::Err((err, None))
-                        }
-                        #[cfg(not(debug_assertions))]
-                        if do_log_line_errors {
-                            return LineResult::Err((err, Some(line)))
-                        } else {
-                            return LineResult::Err((err, None))
-                        }
-                    };
-
-                    if self.patterns.platform.full_timestamp {
-                        LineResult::Ok(line)
-                    } else if line.timestamp < file.timestamp {
-                        LineResult::Rollover(line)
-                    } else {
-                        LineResult::Ok(line)
+                .filter_map(|(i, line)| {
+                    regex_patterns::platform_for_line(&line.text).map(|result| (i, result))
+                })
+                .collect();
+
+            // The sessions in this chunk, and the index of the line each one starts from.
+            let mut chunk_sessions: Vec<(usize, Arc<ActiveSession>)> =
+                vec![(0, state.session.clone())];
+            for (i, result) in version_lines {
+                let line_num = (first_line_num + i) as u32;
+                let result = result.and_then(|(version, platform)| {
+                    Ok((self.patterns.get(&version, platform)?, version))
+                });
+                let (patterns, version) = match result {
+                    // Forced patterns are used for every session, whatever the version line says.
+                    _ if log_file.patterns_source == PatternsSource::Override => {
+                        (log_file.patterns.clone(), log_file.version.clone())
                     }
-                }).collect();
-
-        let (mut ok_results, results): (Vec<Line>, Vec<LineResult>) =
-            results.into_par_iter().partition_map(|lr| match lr {
-                LineResult::Ok(line) => Either::Left(line),
-                other => Either::Right(other),
-            });
-
-        let (mut rollover_results, err_results): (Vec<Line>, Vec<(Error, Option<String>)>) =
-            results.into_par_iter().partition_map(|lr| match lr {
-                LineResult::Rollover(line) => Either::Left(line),
-                LineResult::Err((line, error)) => Either::Right((line, error)),
-                _ => unreachable!(),
-            });
-
-        // For any line where there was potential date rollover, we need to adjust the timestamp.
-        rollover_results.par_sort_unstable_by_key(|line| line.line_num);
-
-        let mut additional_days = TimeDelta::days(0);
-        for mut line in rollover_results {
-            line.timestamp += additional_days;
-            if line.timestamp < file.timestamp {
-                additional_days += TimeDelta::days(1);
-                line.timestamp += TimeDelta::days(1);
+                    Ok(result) => result,
+                    Err(err) => {
+                        log::warn!(
+                            "Ignoring version line {} in '{}': {}",
+                            line_num,
+                            source,
+                            err
+                        );
+                        continue;
+                    }
+                };
+                let Some(mut new_session) = parse_session(
+                    &chunk[i].text,
+                    line_num,
+                    file.id,
+                    &patterns.platform.version,
+                ) else {
+                    continue;
+                };
+                new_session.id = *next_session_id;
+                *next_session_id += 1;
+                log::debug!(
+                    "Found session {} with version {} at line {} of '{}'",
+                    new_session.id,
+                    version,
+                    line_num,
+                    source
+                );
+                state.session = Arc::new(ActiveSession {
+                    id: Some(new_session.id),
+                    patterns,
+                    version,
+                });
+                chunk_sessions.push((i, state.session.clone()));
+                sender
+                    .send(ParserOutput::Session(new_session))
+                    .map_err(|_| Error::Disconnected)?;
             }
-            ok_results.push(line);
-        }
 
-        let ignored_err_count = err_results
-            .par_iter()
-            .filter(|(err, _)| matches!(err, Error::NoDomain | Error::IgnoredEvent))
-            .count();
+            let results: Vec<LineResult> =
+                // For full timestamp, we can parse all lines in parallel.
+                chunk
+                    .into_par_iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let session_index = chunk_sessions.partition_point(|(start, _)| *start <= i) - 1;
+                        let session = chunk_sessions[session_index].1.as_ref();
+                        let line_num = (first_line_num + i) as u64;
+                        let res = self.parse_line(&line, line_num, file, session);
+
+                        let Ok(parsed) = res else {
+                            let error = res.unwrap_err();
+                            // Only reduce lines which failed, not lines which were deliberately ignored.
+                            let signature = (!is_ignored(&error))
+                                .then(|| reduce_line(&line.text, &session.patterns));
+                            return LineResult::Err(LineError {
+                                error,
+                                line_num: line_num as u32,
+                                signature,
+                                line: do_log_line_errors.then_some(line.text),
+                            });
+                        };
+                        let line = parsed;
+
+                        if session.patterns.platform.full_timestamp {
+                            LineResult::Ok(line)
+                        } else if line.timestamp < file.timestamp {
+                            LineResult::Rollover(line)
+                        } else {
+                            LineResult::Ok(line)
+                        }
+                    }).collect();
+
+            let (mut ok_results, results): (Vec<Line>, Vec<LineResult>) =
+                results.into_par_iter().partition_map(|lr| match lr {
+                    LineResult::Ok(line) => Either::Left(line),
+                    other => Either::Right(other),
+                });
+
+            let (mut rollover_results, err_results): (Vec<Line>, Vec<LineError>) =
+                results.into_par_iter().partition_map(|lr| match lr {
+                    LineResult::Rollover(line) => Either::Left(line),
+                    LineResult::Err(error) => Either::Right(error),
+                    _ => unreachable!(),
+                });
+
+            // For any line where there was potential date rollover, we need to adjust the timestamp.
+            // `additional_days` is carried between chunks, as rollover can happen at any point in the file.
+            rollover_results.par_sort_unstable_by_key(|line| line.line_num);
+
+            for mut line in rollover_results {
+                line.timestamp += state.additional_days;
+                if line.timestamp < file.timestamp {
+                    state.additional_days += TimeDelta::days(1);
+                    line.timestamp += TimeDelta::days(1);
+                }
+                ok_results.push(line);
+            }
 
-        #[cfg(debug_assertions)]
-        if do_reduce_line_errors {
-            let mut errors: HashMap<String, (Error, usize)> = HashMap::new();
+            for error in &err_results {
+                *state.error_kinds.entry(error.error.kind()).or_insert(0) += 1;
+            }
 
-            for (err, line) in err_results {
-                if !matches!(err, Error::NoDomain | Error::IgnoredEvent) {
-                    let entry = errors.entry(line.unwrap()).or_insert((err, 0));
+            let (ignored_errors, failed_errors): (Vec<LineError>, Vec<LineError>) = err_results
+                .into_iter()
+                .partition(|error| error.signature.is_none());
+            state.ignored_err_count += ignored_errors.len();
+
+            #[cfg(debug_assertions)]
+            if do_reduce_line_errors {
+                for error in &failed_errors {
+                    let entry = state
+                        .reduced_errors
+                        .entry(error.signature.clone().unwrap())
+                        .or_insert((error.error.to_string(), 0));
                     entry.1 += 1;
                 }
+            } else if do_log_line_errors {
+                for error in &failed_errors {
+                    log::trace!(
+                        "Failed to parse line with '{}': '{}'",
+                        error.error,
+                        error.line.as_ref().unwrap()
+                    );
+                }
             }
 
-            for (line, (err, count)) in errors {
-                log::trace!(
-                    "Failed to parse line {} times with '{}': '{}'",
-                    count,
-                    err,
-                    line
-                );
-            }
-        } else if do_log_line_errors {
-            for (err, line) in err_results {
-                if !matches!(err, Error::NoDomain | Error::IgnoredEvent) {
-                    log::trace!("Failed to parse line with '{}': '{}'", err, line.unwrap());
+            #[cfg(not(debug_assertions))]
+            if do_log_line_errors {
+                for error in &failed_errors {
+                    log::trace!(
+                        "Failed to parse line with '{}': '{}'",
+                        error.error,
+                        error.line.as_ref().unwrap()
+                    );
                 }
             }
-        }
 
-        #[cfg(not(debug_assertions))]
-        if do_log_line_errors {
-            for (err, line) in err_results {
-                if !matches!(err, Error::NoDomain | Error::IgnoredEvent) {
-                    log::trace!("Failed to parse line with '{}': '{}'", err, line.unwrap());
-                }
+            if !failed_errors.is_empty() {
+                let parse_errors = failed_errors
+                    .into_iter()
+                    .map(|error| ParseError {
+                        file_id: file.id,
+                        line_num: error.line_num,
+                        error: error.error.kind().to_string(),
+                        message: error.error.to_string(),
+                        signature: error.signature.unwrap(),
+                    })
+                    .collect();
+                sender
+                    .send(ParserOutput::Errors(parse_errors))
+                    .map_err(|_| Error::Disconnected)?;
+            }
+
+            state.ok_count += ok_results.len();
+            state.unknown_count += ok_results
+                .par_iter()
+                .filter(|line| line.event_type == EventType::Unknown)
+                .count();
+
+            sender
+                .send(ParserOutput::Lines(ok_results))
+                .map_err(|_| Error::Disconnected)?;
+
+            if let Some(err) = failure {
+                return Err(err);
             }
         }
+    }
+
+    /// The report for a file which has been fully parsed.
+    fn file_report(&self, index: usize, state: FileState) -> FileReport {
+        let FileState {
+            file,
+            line_count,
+            ok_count,
+            unknown_count,
+            ignored_err_count,
+            error_kinds,
+            skipped_regions,
+            #[cfg(debug_assertions)]
+            reduced_errors,
+            start_time,
+            ..
+        } = state;
+        let source = &self.files[index].source;
+
+        #[cfg(debug_assertions)]
+        for (line, (err, count)) in reduced_errors {
+            log::trace!(
+                "Failed to parse line {} times with '{}': '{}'",
+                count,
+                err,
+                line
+            );
+        }
 
-        let error_count = (line_count - ok_results.len() - ignored_err_count) as u64;
+        let error_count = (line_count - ok_count - ignored_err_count) as u64;
 
         log::debug!(
-            "Parsed {} lines from '{}' ({} CBL lines skipped due to error, {} insignificant lines ignored)",
-            ok_results.len(),
-            &file.path,
+            "Parsed {} lines from '{}' ({} unknown events, {} CBL lines skipped due to error, {} insignificant lines ignored)",
+            ok_count,
+            source,
+            unknown_count,
             error_count,
             ignored_err_count,
         );
 
-        Ok(ParserOutput {
-            file,
-            lines: ok_results,
+        FileReport {
+            file_id: file.id,
+            path: source.to_string(),
+            version: file.version,
+            platform: file.platform,
+            patterns_source: file.patterns_source,
+            hash: file.hash,
+            line_count: line_count as u64,
+            parsed_count: ok_count as u64,
+            unknown_count: unknown_count as u64,
             error_count,
-        })
+            ignored_count: ignored_err_count as u64,
+            errors: error_kinds
+                .into_iter()
+                .map(|(kind, count)| (kind.to_string(), count))
+                .collect(),
+            skipped_regions,
+            duration: start_time.elapsed(),
+        }
     }
 
     fn parse_line(
         &self,
-        line: &str,
+        line: &RawLine,
         line_num: u64,
         file: &File,
-        base_date: NaiveDate,
+        session: &ActiveSession,
     ) -> Result<Line> {
-        let domain = parse_domain(line, &self.patterns.platform.domain)?;
-
-        let object_path = parse_object(line, &self.patterns.object);
-
-        let timestamp = parse_timestamp(
-            line,
-            &self.patterns.platform.timestamp,
-            self.patterns.platform.full_timestamp,
-            self.patterns.platform.timestamp_formats.as_slice(),
-        )?;
-
-        let timestamp = match timestamp {
-            Timestamp::Partial(ts) => base_date.and_time(ts),
-            Timestamp::Full(ts) => ts,
+        let patterns = &session.patterns;
+
+        // Binary log entries are already decoded, so their header fields don't need to be parsed from the text.
+        let (header, message) = match &line.entry {
+            Some(entry) => (header_from_entry(entry)?, line.text.clone()),
+            None => (
+                parse_line_header(&line.text, file, patterns)?,
+                strip_header(&line.text, patterns).trim_start().to_string(),
+            ),
         };
 
-        let level = parse_level(
-            line,
-            self.patterns.platform.level.as_ref().unwrap(),
-            &self.patterns.platform.level_names,
-        )?;
+        // Lines logged by the LiteCore tests are matched against the message, so the patterns can be anchored to its
+        // start for both text and binary logs.
+        #[cfg(debug_assertions)]
+        if patterns
+            .tests_only_ignored
+            .iter()
+            .any(|regex| regex.is_match(&message))
+        {
+            return Err(Error::IgnoredEvent);
+        }
 
-        let event = parse_event(line, &self.version, &self.patterns)?;
+        // For a binary log entry, `text` is only its body, so just the message is matched against the events.
+        let event = match parse_event(&line.text, &session.version, patterns) {
+            Err(Error::UnknownEvent) if !self.options.skip_unknown => Event {
+                event_type: EventType::Unknown,
+                data: None,
+            },
+            res => res?,
+        };
 
         let line = Line {
             file_id: file.id,
             line_num: line_num as u32,
-            level,
-            timestamp,
-            domain,
+            level: header.level,
+            timestamp: header.timestamp,
+            domain: header.domain,
             event_type: event.event_type,
             event_data: event.data,
-            object_path,
+            object_path: header.object_path,
+            session_id: session.id,
+            message,
+            offset: line.offset,
+            seq: None,
         };
 
         Ok(line)
     }
 
-    fn find_log_files(path: &Path) -> Result<Vec<PathBuf>> {
-        log::debug!(
-            "Searching for valid log files in file or directory {:?}",
-            path
-        );
-        let files = if path.is_dir() {
-            let dir = std::fs::read_dir(path)?;
-            dir.into_iter()
-                .filter_map(|entry| entry.ok())
-                .map(|entry| entry.path())
-                .filter(|path| path.is_file())
-                .filter(|path| match regex_patterns::patterns_for_file(path) {
-                    Err(err) => {
-                        log::error!("Error validating file {:?}: {}", path, err);
-                        false
-                    }
-                    Ok((_, version)) => {
-                        log::debug!("Found valid log file {:?} with version {}", path, version);
-                        true
-                    }
+    /// The log files within `sources`, with the patterns to parse each of them. Files with no version line may take
+    /// their patterns from `existing` files, as well as from the other `sources`.
+    fn log_files(
+        sources: Vec<LogSource>,
+        existing: &[LogFile],
+        options: &Options,
+        cache: &PatternsCache,
+    ) -> Result<Vec<LogFile>> {
+        if let Some((patterns, version)) = forced_patterns(options, cache)? {
+            log::info!(
+                "Parsing all files with version {} and platform '{}'",
+                version,
+                patterns.platform.name
+            );
+            return Ok(sources
+                .into_iter()
+                .map(|source| {
+                    LogFile::new(
+                        source,
+                        patterns.clone(),
+                        version.clone(),
+                        PatternsSource::Override,
+                    )
                 })
-                .collect()
-        } else {
-            match regex_patterns::patterns_for_file(path) {
-                Err(err) => {
-                    log::error!("Error validating file {:?}: {}", path, err);
-                    vec![]
-                }
-                Ok((_, version)) => {
-                    log::debug!("Found valid log file {:?} with version {}", path, version);
-                    vec![path.to_path_buf()]
+                .collect());
+        }
+
+        let mut files = vec![];
+        let mut without_version = vec![];
+        for source in sources {
+            let result = regex_patterns::platform_for_file(&source)
+                .and_then(|(version, platform)| Ok((cache.get(&version, platform)?, version)));
+            match result {
+                Ok((patterns, version)) => {
+                    log::debug!(
+                        "Found valid log file '{}' with version {} and platform '{}'",
+                        source,
+                        version,
+                        patterns.platform.name
+                    );
+                    files.push(LogFile::new(
+                        source,
+                        patterns,
+                        version,
+                        PatternsSource::VersionLine,
+                    ));
                 }
+                Err(Error::NotLogs(_)) => without_version.push(source),
+                Err(err) => log::error!("Error validating file '{}': {}", source, err),
             }
-        };
+        }
+
+        let candidates: Vec<&LogFile> = existing.iter().chain(&files).collect();
+        let siblings: Vec<LogFile> = without_version
+            .into_iter()
+            .filter_map(|source| LogFile::from_sibling(source, &candidates))
+            .collect();
+        files.extend(siblings);
         Ok(files)
     }
 }
 
-enum LineResult {
-    Ok(Line),
-    Rollover(Line),
-    /// Error and reduced line
-    Err((Error, Option<String>)),
+impl Parser {
+    /// Prepare to update a database which already has the `existing` files from a previous run. Files which haven't
+    /// changed since are skipped, as long as the file they were rotated from was skipped too, as their line numbers
+    /// continue from it. Files which were parsed before keep their IDs, and new files are given new IDs. Sessions are
+    /// numbered from `first_session_id`.
+    /// Returns the IDs of the files to delete from the database before parsing, as they've changed or are no longer
+    /// found.
+    pub fn plan_update(&mut self, existing: Vec<File>, first_session_id: u32) -> Result<Vec<u32>> {
+        self.first_session_id = first_session_id;
+        let mut new_ids = existing.iter().map(|file| file.id + 1).max().unwrap_or(0)..;
+        let mut existing: HashMap<(String, Option<String>), File> = existing
+            .into_iter()
+            .map(|file| ((file.path.clone(), file.archive_path.clone()), file))
+            .collect();
+        let mut stale = vec![];
+
+        // Files are sorted so each file's predecessor comes before it.
+        for index in 0..self.files.len() {
+            let log_file = &self.files[index];
+            let key = (
+                log_file.source.path.to_string_lossy().to_string(),
+                log_file.source.archive_path(),
+            );
+            let Some(old) = existing.remove(&key) else {
+                self.files[index].id = new_ids.next().unwrap();
+                continue;
+            };
+
+            let (predecessor_id, predecessor_end) = match log_file.predecessor {
+                Some(predecessor) => (
+                    Some(self.files[predecessor].id),
+                    self.files[predecessor].parsed_end,
+                ),
+                None => (None, Some(0)),
+            };
+            let unchanged = old.line_count.is_some()
+                && old.predecessor_id == predecessor_id
+                && predecessor_end == Some(old.first_line_num)
+                && old.version == log_file.version.to_string()
+                && old.platform == log_file.patterns.platform.name
+                && old.patterns_source == log_file.patterns_source
+                && log_file.source.is_unchanged(&old)?;
+
+            let log_file = &mut self.files[index];
+            log_file.id = old.id;
+            if unchanged {
+                log_file.parsed_end = old
+                    .line_count
+                    .map(|line_count| old.first_line_num + line_count);
+            } else {
+                log::debug!("'{}' has changed, parsing it again", log_file.source);
+                stale.push(old.id);
+            }
+        }
+
+        for old in existing.into_values() {
+            log::info!(
+                "Removing '{}', which is no longer found",
+                LogSource::from_parts(old.path.into(), old.archive_path.as_deref())
+            );
+            stale.push(old.id);
+        }
+        Ok(stale)
+    }
 }
 
-struct ParserIter<'a> {
-    parser: &'a Parser,
-    index: usize,
+/// Parses logs which are still being written. Each `poll` parses the lines written since the previous poll, continuing
+/// each file from where it stopped, and starts parsing any log files created since, i.e. by rotation.
+/// The output is the same as `Parser::parse`, except that files are never complete, so there is no `FileComplete`.
+pub struct Follower {
+    parser: Parser,
+    path: PathBuf,
+    filter: FileFilter,
+    /// The progress through each file in `parser.files`, or `None` if the file failed to parse.
+    states: Vec<Option<FileState>>,
+    /// The paths on disk of the files in `parser.files`.
+    known_paths: HashSet<PathBuf>,
+    /// The size of each file which didn't contain any logs when it was found. They're only checked again once their
+    /// size changes, i.e. once a version line has been written to a new file.
+    rejected_paths: HashMap<PathBuf, u64>,
+    next_session_id: u32,
 }
 
-impl<'a> Iterator for ParserIter<'a> {
-    type Item = ParserOutput;
+impl Follower {
+    /// Follow the logs in the file or directory at `path`. Nothing is parsed until the first `poll`, and there don't
+    /// have to be any logs at `path` yet.
+    pub fn new(path: &Path, options: Options) -> Result<Self> {
+        let filter = FileFilter::new(&options.include, &options.exclude, options.max_depth)?;
+        // Check the forced patterns now, rather than on the first poll.
+        let patterns = PatternsCache::default();
+        forced_patterns(&options, &patterns)?;
+        Ok(Self {
+            parser: Parser {
+                files: vec![],
+                root: source::root_dir(path).to_path_buf(),
+                options,
+                first_session_id: 0,
+                patterns,
+            },
+            path: path.to_path_buf(),
+            filter,
+            states: vec![],
+            known_paths: HashSet::new(),
+            rejected_paths: HashMap::new(),
+            next_session_id: 0,
+        })
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= self.parser.files.len() {
-            return None;
+    /// Parse the lines written since the last poll, then any new log files.
+    pub fn poll(&mut self, sender: &SyncSender<ParserOutput>) -> Result<()> {
+        // Existing files are parsed first, so a rotated file's predecessor is finished before the new file starts.
+        for index in 0..self.states.len() {
+            let Some(state) = &mut self.states[index] else {
+                continue;
+            };
+            if let Err(err) =
+                self.parser
+                    .parse_lines(index, state, &mut self.next_session_id, sender)
+            {
+                self.fail(index, err, sender)?;
+            }
         }
-        self.index += 1;
-        match self.parser.parse_file(self.index - 1) {
-            Ok(output) => Some(output),
-            Err(err) => {
-                log::error!(
-                    "Error parsing file '{}': {}",
-                    self.parser.files[self.index - 1].to_string_lossy(),
-                    err
+
+        for mut log_file in self.find_new_files()? {
+            let index = self.parser.files.len();
+            log_file.id = index as u32;
+            log_file.predecessor = self.latest_file(&log_file);
+            let first_line_num = log_file
+                .predecessor
+                .and_then(|predecessor| self.states[predecessor].as_ref())
+                .map_or(0, |state| state.next_line_num);
+            if let Some(predecessor) = log_file.predecessor {
+                log::debug!(
+                    "'{}' was rotated from '{}'",
+                    log_file.source,
+                    self.parser.files[predecessor].source
                 );
-                None
             }
+            log::info!("Following '{}'", log_file.source);
+            let follow = log_file.source.can_grow();
+            self.parser.files.push(log_file);
+            self.states.push(None);
+
+            let result = self
+                .parser
+                .start_file(index, first_line_num, follow, sender)
+                .and_then(|state| {
+                    let state = self.states[index].insert(state);
+                    self.parser
+                        .parse_lines(index, state, &mut self.next_session_id, sender)
+                });
+            if let Err(err) = result {
+                self.fail(index, err, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The log files which have been created since the last poll, in the order they were started.
+    fn find_new_files(&mut self) -> Result<Vec<LogFile>> {
+        let paths: Vec<PathBuf> = source::find_files(&self.path, &self.filter)?
+            .into_iter()
+            .filter(|path| !self.known_paths.contains(path))
+            .filter(|path| self.rejected_paths.get(path) != file_len(path).as_ref())
+            .collect();
+        if paths.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let sources = source::expand_files(&self.parser.root, paths.clone(), &self.filter);
+        let mut files = Parser::log_files(
+            sources,
+            &self.parser.files,
+            &self.parser.options,
+            &self.parser.patterns,
+        )?;
+        for path in paths {
+            if files.iter().any(|file| file.source.path == path) {
+                self.known_paths.insert(path);
+            } else if let Some(len) = file_len(&path) {
+                self.rejected_paths.insert(path, len);
+            }
+        }
+        files.sort_by_cached_key(|file| {
+            (
+                timestamp_from_filename(file.source.file_stem()),
+                file.source.clone(),
+            )
+        });
+        Ok(files)
+    }
+
+    /// The index of the latest file of the same level in the same directory as `log_file`, which it was rotated from.
+    fn latest_file(&self, log_file: &LogFile) -> Option<usize> {
+        log_file.level?;
+        let directory = log_file.source.directory();
+        self.parser
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| {
+                file.level == log_file.level && file.source.directory() == directory
+            })
+            .max_by_key(|(_, file)| timestamp_from_filename(file.source.file_stem()))
+            .map(|(index, _)| index)
+    }
+
+    /// Stop following the file at `index` after an error.
+    fn fail(&mut self, index: usize, err: Error, sender: &SyncSender<ParserOutput>) -> Result<()> {
+        if let Error::Disconnected = err {
+            return Err(err);
+        }
+        self.states[index] = None;
+        let path = self.parser.files[index].source.to_string();
+        log::error!("Error parsing file '{}': {}", path, err);
+        let failed = FailedFile {
+            path,
+            error: err.to_string(),
+        };
+        sender
+            .send(ParserOutput::FileFailed(failed))
+            .map_err(|_| Error::Disconnected)
+    }
+}
+
+/// The size of the file at `path`, or `None` if it can't be read.
+fn file_len(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|meta| meta.len())
+}
+
+/// The patterns forced by `Options::cbl_version` and `Options::platform`, if given.
+fn forced_patterns(
+    options: &Options,
+    cache: &PatternsCache,
+) -> Result<Option<(Patterns, semver::Version)>> {
+    match (&options.cbl_version, &options.platform) {
+        (None, None) => Ok(None),
+        (Some(version), Some(platform)) => {
+            let patterns = cache.get(version, platform)?;
+            Ok(Some((patterns, version.clone())))
+        }
+        _ => Err(Error::CannotParse(
+            "Both a CBL version and a platform are needed to force the patterns".to_string(),
+        )),
+    }
+}
+
+impl LogFile {
+    fn new(
+        source: LogSource,
+        patterns: Patterns,
+        version: semver::Version,
+        patterns_source: PatternsSource,
+    ) -> Self {
+        let level = level_from_filename(source.file_stem());
+        Self {
+            id: 0,
+            parsed_end: None,
+            source,
+            patterns,
+            version,
+            level,
+            predecessor: None,
+            patterns_source,
+        }
+    }
+
+    /// Use the patterns of a file in the same directory for a file with no version line, such as a rotated file or a
+    /// file whose start was truncated. An earlier file of the same level is preferred, as it is most likely to be from
+    /// the same app launch. Returns `None` if there is no such file, or the file doesn't look like logs for the
+    /// sibling's patterns.
+    fn from_sibling(source: LogSource, files: &[&LogFile]) -> Option<Self> {
+        let level = level_from_filename(source.file_stem());
+        let directory = source.directory();
+        let in_same_dir = || {
+            files
+                .iter()
+                .filter(|file| file.source.directory() == directory)
+        };
+        let timestamp = |source: &LogSource| timestamp_from_filename(source.file_stem());
+        let sibling = in_same_dir()
+            .filter(|file| level.is_some() && file.level == level)
+            .filter(|file| timestamp(&file.source) <= timestamp(&source))
+            .max_by_key(|file| timestamp(&file.source))
+            .or_else(|| in_same_dir().next());
+        let Some(sibling) = sibling else {
+            log::error!(
+                "Error validating file '{}': {}",
+                source,
+                Error::NotLogs(PathBuf::from(source.to_string()))
+            );
+            return None;
+        };
+
+        if !has_timestamps(&source, &sibling.patterns) {
+            log::debug!(
+                "Ignoring file '{}' with no version line, which doesn't match the patterns of '{}'",
+                source,
+                sibling.source
+            );
+            return None;
         }
+        log::warn!(
+            "File '{}' has no version line, parsing with version {} and platform '{}' from '{}'",
+            source,
+            sibling.version,
+            sibling.patterns.platform.name,
+            sibling.source
+        );
+        Some(Self::new(
+            source,
+            sibling.patterns.clone(),
+            sibling.version.clone(),
+            PatternsSource::Sibling,
+        ))
+    }
+}
+
+/// Whether any of the first lines of the file have a timestamp in the format of `patterns`. Binary log entries always
+/// have a timestamp.
+fn has_timestamps(source: &LogSource, patterns: &Patterns) -> bool {
+    let Ok(lines) = read_lines(source) else {
+        return false;
+    };
+    lines
+        .take(SIBLING_CHECK_LINES)
+        .filter_map(Result::ok)
+        .any(|line| line.entry.is_some() || patterns.platform.timestamp.is_match(&line.text))
+}
+
+enum LineResult {
+    Ok(Line),
+    Rollover(Line),
+    Err(LineError),
+}
+
+struct LineError {
+    error: Error,
+    line_num: u32,
+    /// The reduced line, or `None` if the line was ignored rather than failing to parse.
+    signature: Option<String>,
+    /// The full line, only kept when parse errors are being logged.
+    line: Option<String>,
+}
+
+/// Lines which aren't CBL lines, or are deliberately ignored, are not considered parse errors.
+fn is_ignored(error: &Error) -> bool {
+    matches!(error, Error::NoDomain | Error::IgnoredEvent)
+}
+
+/// The fields common to every line, which precede the message.
+struct LineHeader {
+    domain: String,
+    object_path: Option<String>,
+    timestamp: NaiveDateTime,
+    level: Level,
+}
+
+/// Parse the header fields of a text line with the platform regexes.
+fn parse_line_header(line: &str, file: &File, patterns: &Patterns) -> Result<LineHeader> {
+    let domain = parse_domain(line, &patterns.platform.domain)?;
+
+    let object_path = parse_object(line, &patterns.object);
+
+    let timestamp = parse_timestamp(
+        line,
+        &patterns.platform.timestamp,
+        patterns.platform.full_timestamp,
+        patterns.platform.timestamp_formats.as_slice(),
+    )?;
+
+    let timestamp = match timestamp {
+        Timestamp::Partial(ts) => file.timestamp.date().and_time(ts),
+        Timestamp::Full(ts) => ts,
+    };
+
+    let level = parse_level(
+        line,
+        patterns.platform.level.as_ref().unwrap(),
+        &patterns.platform.level_names,
+    )?;
+
+    Ok(LineHeader {
+        domain,
+        object_path,
+        timestamp,
+        level,
+    })
+}
+
+/// Take the header fields from an already decoded binary log entry.
+fn header_from_entry(entry: &Entry) -> Result<LineHeader> {
+    if entry.domain.is_empty() {
+        return Err(Error::NoDomain);
     }
+
+    // Objects are logged with a trailing '/', which the object regex excludes for text lines.
+    let object_path = entry.object.as_deref().map(|object| {
+        let object = object.strip_suffix('/').unwrap_or(object);
+        demangle_object(object).to_string()
+    });
+
+    Ok(LineHeader {
+        domain: entry.domain.clone(),
+        object_path,
+        timestamp: entry.timestamp,
+        level: entry.level,
+    })
 }
 
 fn parse_domain(line: &str, regex: &Regex) -> Result<String> {
@@ -395,13 +1303,47 @@ fn parse_object(line: &str, regex: &Regex) -> Option<String> {
 
     let obj_str = caps.name("obj")?.as_str();
 
-    let obj_str = if let Some(obj_caps) = JNI_OBJECT_REGEX.captures(obj_str) {
+    Some(demangle_object(obj_str).to_string())
+}
+
+/// Strip the JNI mangling from an object name, if it has any.
+fn demangle_object(object: &str) -> &str {
+    if let Some(obj_caps) = JNI_OBJECT_REGEX.captures(object) {
         obj_caps.name("object").unwrap().as_str()
     } else {
-        obj_str
-    };
+        object
+    }
+}
+
+/// Create a `Session` from the captures of the version line regex, if `line` is a version line.
+/// The session ID is not known here, and must be assigned by the caller.
+fn parse_session(line: &str, line_num: u32, file_id: u32, regex: &Regex) -> Option<Session> {
+    let caps = regex.captures(line)?;
+    let capture = |name: &str| caps.name(name).map(|mat| mat.as_str().to_string());
+
+    Some(Session {
+        id: 0,
+        file_id,
+        line_num,
+        version: capture("ver")?,
+        platform: capture("plat"),
+        os: capture("os"),
+        build: capture("build").and_then(|build| build.parse().ok()),
+        commit: capture("commit"),
+    })
+}
 
-    Some(obj_str.to_string())
+fn parse_header(line: &str, file_id: u32) -> Option<Vec<Metadata>> {
+    Some(
+        decoder::parse_header(line)?
+            .into_iter()
+            .map(|(key, value)| Metadata {
+                file_id,
+                key,
+                value,
+            })
+            .collect(),
+    )
 }
 
 #[derive(Debug, Clone)]
@@ -447,7 +1389,8 @@ lazy_static! {
     static ref QUOTE_REGEX: Regex = Regex::new(r#"^'.*'"#).unwrap();
 }
 
-fn reduce_line(line: &str, patterns: &Patterns) -> String {
+/// Strip the header (timestamp, domain and level) from the line, leaving the message.
+fn strip_header<'a>(line: &'a str, patterns: &Patterns) -> &'a str {
     let domain_end = patterns
         .platform
         .domain
@@ -461,12 +1404,16 @@ fn reduce_line(line: &str, patterns: &Patterns) -> String {
         .and_then(|re| re.find(line))
         .map_or(0, |mat| mat.end());
 
-    // Strip domain and level from the line
-    let line = if domain_end > level_end {
+    if domain_end > level_end {
         &line[domain_end..]
     } else {
         &line[level_end..]
-    };
+    }
+}
+
+fn reduce_line(line: &str, patterns: &Patterns) -> String {
+    // Strip domain and level from the line
+    let line = strip_header(line, patterns);
 
     // Strip any dictionaries from the line
     let dict_mat = DICT_REGEX.find(line);
@@ -514,8 +1461,50 @@ fn reduce_line(line: &str, patterns: &Patterns) -> String {
         })
 }
 
+/// Sort the files into chains of rotated files, each of a single level in a single directory, ordered by the timestamp
+/// in their names. Each file in a chain is linked to the previous file, so its line numbers can continue from it.
+fn link_rotated_files(files: &mut [LogFile]) {
+    files.sort_by_cached_key(|file| {
+        (
+            file.source.directory(),
+            file.level.map(|level| level as u32),
+            timestamp_from_filename(file.source.file_stem()),
+            file.source.clone(),
+        )
+    });
+    for index in 1..files.len() {
+        let (previous, file) = (&files[index - 1], &files[index]);
+        if file.level.is_some()
+            && file.level == previous.level
+            && file.source.directory() == previous.source.directory()
+        {
+            log::debug!("'{}' was rotated from '{}'", file.source, previous.source);
+            files[index].predecessor = Some(index - 1);
+        }
+    }
+}
+
+lazy_static! {
+    /// CBL writes a file for each level, i.e. `cbl_info_1722271812929.cbllog`, named with the time it was started.
+    static ref LEVEL_FILE_REGEX: Regex =
+        Regex::new(r#"^cbl_(?<level>debug|verbose|info|warning|error)_\d+$"#).unwrap();
+}
+
+/// The level of the lines in a CBL log file, from its name, i.e. `cbl_info_1722271812929.cbllog`.
+fn level_from_filename(file_name: &str) -> Option<Level> {
+    let caps = LEVEL_FILE_REGEX.captures(file_name)?;
+    match &caps["level"] {
+        "debug" => Some(Level::Debug),
+        "verbose" => Some(Level::Verbose),
+        "info" => Some(Level::Info),
+        "warning" => Some(Level::Warning),
+        "error" => Some(Level::Error),
+        _ => None,
+    }
+}
+
 fn timestamp_from_filename(file_name: &str) -> Option<NaiveDateTime> {
-    let ts_str = file_name.split('_').last()?;
+    let ts_str = file_name.split('_').next_back()?;
 
     let dt = ts_str
         .parse()
@@ -525,12 +1514,129 @@ fn timestamp_from_filename(file_name: &str) -> Option<NaiveDateTime> {
     Some(dt.naive_utc())
 }
 
-pub(crate) fn read_lines(file_path: &Path) -> Result<Vec<String>> {
-    if decoder::is_encoded(file_path)? {
-        decoder::decode_lines(file_path)
+/// A line read from a log file.
+pub(crate) struct RawLine {
+    /// The byte offset of the line in a text log, or the index of the entry in a binary log.
+    pub offset: u64,
+    /// The text of a line of a text log, or the body (object and message) of a binary log entry. Version lines, headers
+    /// and events are matched against this, so binary entries are never formatted as a whole line to be parsed.
+    pub text: String,
+    /// The decoded entry, for binary logs.
+    pub entry: Option<Entry>,
+}
+
+/// An iterator over the lines of a log file, which reads the file incrementally.
+pub(crate) type LineReader = Box<dyn Iterator<Item = Result<RawLine>> + Send>;
+
+pub(crate) fn read_lines(source: &LogSource) -> Result<LineReader> {
+    open_lines(source.open()?, false)
+}
+
+/// Read the lines of a log file. If `follow` is set, the reader stops before a line or entry which is still being
+/// written at the end of the file, and continues from it once the file has grown.
+fn open_lines(mut reader: Box<dyn ReadSeek>, follow: bool) -> Result<LineReader> {
+    if decoder::starts_with_magic_number(&mut reader)? {
+        let mut decoder = decoder::Decoder::new(reader)?;
+        decoder.set_following(follow);
+        // The offset of each entry is its index, not counting corrupt regions.
+        let mut index = 0_u64;
+        Ok(Box::new(decoder.map(move |entry| {
+            entry.map(|entry| {
+                let offset = index;
+                index += 1;
+                RawLine {
+                    offset,
+                    text: entry.body(),
+                    entry: Some(entry),
+                }
+            })
+        })))
     } else {
-        let contents = std::fs::read_to_string(file_path)?;
-        Ok(contents.lines().map(str::to_string).collect())
+        read_text_lines(reader, 0, follow)
+    }
+}
+
+/// Read the lines of a text log, starting from the given byte offset.
+fn read_text_lines(mut reader: Box<dyn ReadSeek>, offset: u64, follow: bool) -> Result<LineReader> {
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok(Box::new(TextLines {
+        reader: BufReader::new(reader),
+        offset,
+        follow,
+    }))
+}
+
+/// Read the lines surrounding `line_num` in a log file, up to `context` lines either side.
+/// `first_line_num` is the line_num of the first line in the file. `start` is the line_num and offset of a line before
+/// the context, which the file can be read from.
+pub(crate) fn read_context(
+    source: &LogSource,
+    first_line_num: u32,
+    start: (u32, u64),
+    line_num: u32,
+    context: u32,
+) -> Result<Vec<(u32, String)>> {
+    let first = line_num.saturating_sub(context);
+    let last = line_num.saturating_add(context);
+
+    // Binary logs must be decoded from the start, as strings are tokenized across entries.
+    let mut reader = source.open()?;
+    let (start_line_num, lines): (u32, LineReader) =
+        if decoder::starts_with_magic_number(&mut reader)? {
+            let lines = read_lines(source)?
+                .filter(|line| !matches!(line, Err(Error::CorruptBinaryLogs(_, _, _))));
+            (first_line_num, Box::new(lines))
+        } else {
+            (start.0, read_text_lines(reader, start.1, false)?)
+        };
+
+    (start_line_num..)
+        .zip(lines)
+        .skip_while(|(i, _)| *i < first)
+        .take_while(|(i, _)| *i <= last)
+        .map(|(i, line)| {
+            line.map(|line| match line.entry {
+                Some(entry) => (i, entry.to_string()),
+                None => (i, line.text),
+            })
+        })
+        .collect()
+}
+
+struct TextLines<R: BufRead + Seek> {
+    reader: R,
+    offset: u64,
+    /// Whether a line with no newline at the end of the file is still being written, so shouldn't be read yet.
+    follow: bool,
+}
+
+impl<R: BufRead + Seek> Iterator for TextLines<R> {
+    type Item = Result<RawLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = String::new();
+        match self.reader.read_line(&mut text) {
+            Ok(0) => None,
+            Ok(_) if self.follow && !text.ends_with('\n') => {
+                // Read the line again once the rest of it has been written.
+                match self.reader.seek(SeekFrom::Start(self.offset)) {
+                    Ok(_) => None,
+                    Err(err) => Some(Err(Error::Io(err))),
+                }
+            }
+            Ok(len) => {
+                let offset = self.offset;
+                self.offset += len as u64;
+                let trimmed_len = text.trim_end_matches(['\n', '\r']).len();
+                text.truncate(trimmed_len);
+                Some(Ok(RawLine {
+                    offset,
+                    text,
+                    entry: None,
+                }))
+            }
+            Err(err) => Some(Err(Error::Io(err))),
+        }
     }
 }
 
@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{Serialize, Serializer};
+
+use crate::data::PatternsSource;
+
+/// A summary of a call to `parse`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ParseReport {
+    /// A report for each file which was parsed, in the order they were parsed.
+    pub files: Vec<FileReport>,
+    /// Files which were found but could not be parsed.
+    pub failed_files: Vec<FailedFile>,
+    /// Files which were skipped when updating a database, as they haven't changed since they were parsed into it.
+    pub unchanged_files: Vec<String>,
+    /// The total time taken, including writing to the database.
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+/// A summary of the parsing of a single file.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub file_id: u32,
+    pub path: String,
+    /// The CBL version detected for the file.
+    pub version: String,
+    /// The name of the platform detected for the file.
+    pub platform: String,
+    /// How the version and platform were chosen.
+    pub patterns_source: PatternsSource,
+    /// The SHA-256 of the contents of the file, as hex.
+    pub hash: String,
+    /// The number of lines read from the file.
+    pub line_count: u64,
+    /// The number of lines stored in the database, including unknown events.
+    pub parsed_count: u64,
+    /// The number of lines stored with `EventType::Unknown`.
+    pub unknown_count: u64,
+    /// The number of CBL lines which failed to parse.
+    pub error_count: u64,
+    /// The number of lines which aren't CBL lines, or were deliberately ignored.
+    pub ignored_count: u64,
+    /// The number of lines which failed or were ignored, by kind of `Error`.
+    pub errors: BTreeMap<String, u64>,
+    /// Corrupt regions of a binary log which were skipped. Only present when parsing with `Options::recover`.
+    pub skipped_regions: Vec<SkippedRegion>,
+    /// The time taken to parse the file.
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+/// A corrupt region of a binary log which was skipped.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedRegion {
+    /// The byte offset of the start of the corrupt entry.
+    pub offset: u64,
+    /// The number of bytes skipped, up to the next valid entry or the end of the file.
+    pub length: u64,
+    /// The error which the corrupt entry failed to decode with.
+    pub error: String,
+}
+
+/// A file which could not be parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedFile {
+    pub path: String,
+    pub error: String,
+}
+
+impl ParseReport {
+    /// The total number of lines read from all files.
+    pub fn line_count(&self) -> u64 {
+        self.files.iter().map(|f| f.line_count).sum()
+    }
+
+    /// The total number of lines stored in the database.
+    pub fn parsed_count(&self) -> u64 {
+        self.files.iter().map(|f| f.parsed_count).sum()
+    }
+
+    /// The total number of lines stored with `EventType::Unknown`.
+    pub fn unknown_count(&self) -> u64 {
+        self.files.iter().map(|f| f.unknown_count).sum()
+    }
+
+    /// The total number of CBL lines which failed to parse.
+    pub fn error_count(&self) -> u64 {
+        self.files.iter().map(|f| f.error_count).sum()
+    }
+
+    /// The total number of lines which were ignored.
+    pub fn ignored_count(&self) -> u64 {
+        self.files.iter().map(|f| f.ignored_count).sum()
+    }
+
+    /// The number of lines which failed or were ignored across all files, by kind of `Error`.
+    pub fn errors(&self) -> BTreeMap<String, u64> {
+        let mut errors = BTreeMap::new();
+        for (kind, count) in self.files.iter().flat_map(|f| &f.errors) {
+            *errors.entry(kind.clone()).or_insert(0) += count;
+        }
+        errors
+    }
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
@@ -0,0 +1,646 @@
+use std::{
+    fmt,
+    io::{BufRead, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::{
+    archive::{ArchiveIndex, ArchiveKind},
+    data::File,
+    decoder, Error, Result,
+};
+
+/// Separates the path of an archive from the path of a file within it, i.e. `logs.zip!/cbl_info_1722271812929.txt`.
+pub(crate) const ARCHIVE_SEPARATOR: &str = "!/";
+
+/// The number of bytes at the start of a file which are checked for NULL bytes, to skip binary files which aren't
+/// binary logs.
+const BINARY_CHECK_BYTES: u64 = 8192;
+/// The number of bytes at the start of text logs read from a stream which are kept, so they can be read again while
+/// the logs are searched for a version line.
+const STREAM_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+
+/// A reader which can be used by the `Decoder`, whether the logs are read from a file or from memory.
+pub(crate) trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where a log file is read from. Either a file on disk, logs read from a stream (i.e. stdin), or a file within an
+/// archive, which may itself be within other archives (i.e. a zip file inside a support bundle).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct LogSource {
+    /// The path of the file on disk, or the label of logs read from a stream.
+    pub path: PathBuf,
+    /// The path within each nested archive, starting from the archive at `path`. Empty for a plain file.
+    pub entries: Vec<String>,
+    /// The logs read from a stream. `None` for a file on disk.
+    stream: Unkeyed<Option<Stream>>,
+    /// The index of the archive which each of `entries` is within, which is found the first time the archive is read.
+    /// An index is shared by the clones of a source, and by the sources of the other files within the same archive.
+    archives: Unkeyed<Vec<Arc<OnceLock<ArchiveIndex>>>>,
+}
+
+/// A field of a `LogSource` which isn't part of its identity, so sources are compared, ordered and hashed by their
+/// paths alone.
+#[derive(Debug, Clone, Default)]
+struct Unkeyed<T>(T);
+
+impl<T> PartialEq for Unkeyed<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Unkeyed<T> {}
+
+impl<T> PartialOrd for Unkeyed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Unkeyed<T> {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl<T> std::hash::Hash for Unkeyed<T> {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+/// Logs read from a stream, which can only be read once.
+#[derive(Clone)]
+enum Stream {
+    /// Binary logs, which are decoded by seeking within them, or an archive. These are copied to a temporary file.
+    Spilled(Arc<NamedTempFile>),
+    /// Text logs, which are parsed as they're read.
+    Text(Arc<Mutex<StreamBuffer>>),
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spilled(file) => f.debug_tuple("Spilled").field(&file.path()).finish(),
+            Self::Text(_) => f.write_str("Text"),
+        }
+    }
+}
+
+/// Text logs read from a stream. The start of the stream is kept, so it can be read again while its version and
+/// timestamp are found, before it's parsed from the start.
+struct StreamBuffer {
+    reader: Box<dyn Read + Send>,
+    /// Up to `STREAM_BUFFER_BYTES` bytes from the start of the stream.
+    start: Vec<u8>,
+    /// The number of bytes read from `reader`.
+    read_len: u64,
+}
+
+/// A reader of text logs from a stream, which can read the start of the stream any number of times, but can only
+/// continue past it from where the stream was last read.
+struct StreamReader {
+    buffer: Arc<Mutex<StreamBuffer>>,
+    pos: u64,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let buffer = &mut *buffer;
+        let start_len = buffer.start.len() as u64;
+        if self.pos < start_len {
+            let len = (&buffer.start[self.pos as usize..]).read(buf)?;
+            self.pos += len as u64;
+            return Ok(len);
+        }
+        if self.pos != buffer.read_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Logs read from a stream can't be read again",
+            ));
+        }
+        let len = buffer.reader.read(buf)?;
+        if self.pos == start_len && buffer.start.len() + len <= STREAM_BUFFER_BYTES {
+            buffer.start.extend_from_slice(&buf[..len]);
+        }
+        buffer.read_len += len as u64;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for StreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Logs read from a stream can't be seeked from the end",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+impl LogSource {
+    pub fn file(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: vec![],
+            stream: Unkeyed::default(),
+            archives: Unkeyed::default(),
+        }
+    }
+
+    /// Logs read from `reader`, whose start is read to tell whether they're text or binary. Text logs are parsed as
+    /// they're read, while binary logs and archives, which are read more than once, are copied to a temporary file.
+    /// `label` is used in place of the file name.
+    pub fn stream(label: &str, mut reader: impl BufRead + Send + 'static) -> Result<Self> {
+        let mut start = vec![];
+        (&mut reader)
+            .take(BINARY_CHECK_BYTES)
+            .read_to_end(&mut start)?;
+        let stream = if ArchiveKind::from_name(label).is_some()
+            || decoder::starts_with_magic_number(&mut start.as_slice())?
+        {
+            let mut file = NamedTempFile::new()?;
+            file.write_all(&start)?;
+            std::io::copy(&mut reader, &mut file)?;
+            Stream::Spilled(Arc::new(file))
+        } else {
+            Stream::Text(Arc::new(Mutex::new(StreamBuffer {
+                reader: Box::new(reader),
+                read_len: start.len() as u64,
+                start,
+            })))
+        };
+        Ok(Self {
+            path: PathBuf::from(label),
+            entries: vec![],
+            stream: Unkeyed(Some(stream)),
+            archives: Unkeyed::default(),
+        })
+    }
+
+    /// A source from the columns of the `files` table, where `archive_path` is `entries` joined by
+    /// `ARCHIVE_SEPARATOR`.
+    pub fn from_parts(path: PathBuf, archive_path: Option<&str>) -> Self {
+        let entries: Vec<String> = archive_path
+            .map(|archive_path| {
+                archive_path
+                    .split(ARCHIVE_SEPARATOR)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            archives: Unkeyed((0..entries.len()).map(|_| Arc::default()).collect()),
+            entries,
+            stream: Unkeyed::default(),
+        }
+    }
+
+    /// The path within the archive, or `None` for a plain file.
+    pub fn archive_path(&self) -> Option<String> {
+        (!self.entries.is_empty()).then(|| self.entries.join(ARCHIVE_SEPARATOR))
+    }
+
+    /// The name of the file, without any directories.
+    pub fn file_name(&self) -> &str {
+        match self.entries.last() {
+            Some(entry) => entry.rsplit('/').next().unwrap_or(entry),
+            None => self
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The name of the file without its extension, i.e. `cbl_info_1722271812929`.
+    pub fn file_stem(&self) -> &str {
+        let name = self.file_name();
+        Path::new(name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name)
+    }
+
+    /// The directory containing the file, so files which were written together can be grouped.
+    pub fn directory(&self) -> LogSource {
+        let mut directory = self.clone();
+        match directory.entries.last_mut() {
+            Some(entry) => {
+                *entry = entry
+                    .rsplit_once('/')
+                    .map_or(String::new(), |(directory, _)| directory.to_string());
+            }
+            None => {
+                directory.path = self
+                    .path
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+            }
+        }
+        directory
+    }
+
+    /// Open the file for reading. A file within an archive is read straight from the archive, and decompressed as it's
+    /// read.
+    pub fn open(&self) -> Result<Box<dyn ReadSeek>> {
+        self.open_within(self.entries.len())
+    }
+
+    /// Open the file which is `depth` entries into the archives.
+    fn open_within(&self, depth: usize) -> Result<Box<dyn ReadSeek>> {
+        let mut reader: Box<dyn ReadSeek> = match &self.stream.0 {
+            Some(Stream::Spilled(file)) => Box::new(file.reopen()?),
+            Some(Stream::Text(buffer)) => Box::new(StreamReader {
+                buffer: buffer.clone(),
+                pos: 0,
+            }),
+            None => Box::new(std::fs::File::open(&self.path)?),
+        };
+        for (index, entry) in self.entries[..depth].iter().enumerate() {
+            reader = self.archive(index)?.open(reader, entry)?;
+        }
+        Ok(reader)
+    }
+
+    /// The index of the archive which `entries[index]` is within, which is found by reading through the archive the
+    /// first time it's needed.
+    fn archive(&self, index: usize) -> Result<&ArchiveIndex> {
+        let archive = &self.archives.0[index];
+        if let Some(archive) = archive.get() {
+            return Ok(archive);
+        }
+        let found = ArchiveIndex::new(
+            self.open_within(index)?,
+            self.archive_kind(index)?,
+            BINARY_CHECK_BYTES,
+        )?;
+        Ok(archive.get_or_init(|| found))
+    }
+
+    /// The kind of the archive which `entries[index]` is within.
+    fn archive_kind(&self, index: usize) -> Result<ArchiveKind> {
+        let name = match index.checked_sub(1) {
+            Some(parent) => self.entries[parent].clone(),
+            None => self.path.to_string_lossy().to_string(),
+        };
+        ArchiveKind::from_name(&name)
+            .ok_or_else(|| Error::CannotParse(format!("'{}' is not an archive", name)))
+    }
+
+    /// The path of the file on disk relative to `root`, the directory being searched for logs.
+    pub fn relative_path(&self, root: &Path) -> PathBuf {
+        self.path
+            .strip_prefix(root)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| self.path.clone())
+    }
+
+    /// The path relative to `root`, including the path within any archives, with '/' separators.
+    /// This is what `FileFilter` globs are matched against.
+    fn filter_name(&self, root: &Path) -> String {
+        let mut name = path_name(&self.relative_path(root));
+        for entry in &self.entries {
+            name.push_str(ARCHIVE_SEPARATOR);
+            name.push_str(entry);
+        }
+        name
+    }
+
+    /// The size in bytes and the modification time of the file on disk, or of the archive containing the file.
+    /// Logs read from a stream have no modification time, and text logs have a size of 0 as they haven't been read yet.
+    pub fn disk_metadata(&self) -> Result<(u64, Option<NaiveDateTime>)> {
+        match &self.stream.0 {
+            Some(Stream::Spilled(file)) => return Ok((file.as_file().metadata()?.len(), None)),
+            Some(Stream::Text(_)) => return Ok((0, None)),
+            None => (),
+        }
+        let metadata = std::fs::metadata(&self.path)?;
+        let modified = DateTime::<Utc>::from(metadata.modified()?).naive_utc();
+        Ok((metadata.len(), Some(modified)))
+    }
+
+    /// The SHA-256 of the contents of the file, as hex.
+    pub fn hash(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut self.open()?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Whether the file is the same as when `file` was parsed from it. The file is only read if its size or
+    /// modification time have changed, in which case its contents are compared by hash.
+    pub fn is_unchanged(&self, file: &File) -> Result<bool> {
+        let (size, modified) = self.disk_metadata()?;
+        if size == file.size && modified == file.modified {
+            return Ok(true);
+        }
+        if size != file.size && self.entries.is_empty() {
+            return Ok(false);
+        }
+        Ok(self.hash()? == file.hash)
+    }
+
+    /// Whether more logs may be written to the file while it's being parsed. Only plain files on disk can grow.
+    pub fn can_grow(&self) -> bool {
+        self.entries.is_empty() && !self.is_stream()
+    }
+
+    /// Whether the logs were read from a stream, rather than a file on disk.
+    pub fn is_stream(&self) -> bool {
+        self.stream.0.is_some()
+    }
+
+    /// Whether the file could be logs, either binary logs or text. Other binary files are detected from their first
+    /// bytes, so they are skipped without being searched for a version line.
+    fn may_be_logs(&self) -> Result<bool> {
+        let mut data = vec![];
+        let start = match self.entries.len().checked_sub(1) {
+            // The start of each file within an archive was kept when the archive was read, so it isn't read again.
+            Some(last) => self.archive(last)?.head(&self.entries[last])?,
+            None => {
+                self.open()?
+                    .take(BINARY_CHECK_BYTES)
+                    .read_to_end(&mut data)?;
+                data.as_slice()
+            }
+        };
+        Ok(decoder::starts_with_magic_number(&mut &start[..])? || !start.contains(&0))
+    }
+
+    fn child(&self, entry: String, archive: Arc<OnceLock<ArchiveIndex>>) -> Self {
+        let mut child = self.clone();
+        child.entries.push(entry);
+        child.archives.0.push(archive);
+        child
+    }
+}
+
+/// A reader which hashes the contents of a file as it's read for parsing, so the file isn't read again just to hash it.
+/// Clones share the reader and the hash.
+#[derive(Clone)]
+pub(crate) struct HashingReader(Arc<Mutex<HashingState>>);
+
+struct HashingState {
+    reader: Box<dyn ReadSeek>,
+    /// The position of `reader`.
+    pos: u64,
+    /// The number of bytes from the start of the file which have been hashed. Bytes are only hashed once, and in order,
+    /// however the file is seeked.
+    hashed_len: u64,
+    hasher: Sha256,
+}
+
+impl HashingReader {
+    pub fn new(reader: Box<dyn ReadSeek>) -> Self {
+        Self(Arc::new(Mutex::new(HashingState {
+            reader,
+            pos: 0,
+            hashed_len: 0,
+            hasher: Sha256::new(),
+        })))
+    }
+
+    /// The SHA-256 of the contents of the file, as hex. Any of the file which hasn't been read yet is read to finish
+    /// the hash, so this is the same as `LogSource::hash`.
+    pub fn finish(&self) -> Result<String> {
+        let mut state = self.0.lock().unwrap();
+        let pos = state.pos;
+        state.hash_to(u64::MAX)?;
+        state.reader.seek(SeekFrom::Start(pos))?;
+        Ok(format!("{:x}", state.hasher.clone().finalize()))
+    }
+}
+
+impl HashingState {
+    /// Hash the bytes before `end`, or before the end of the file, which haven't been hashed yet. This moves the reader
+    /// without changing `pos`.
+    fn hash_to(&mut self, end: u64) -> std::io::Result<()> {
+        if self.hashed_len < end {
+            self.reader.seek(SeekFrom::Start(self.hashed_len))?;
+            let mut unhashed = (&mut self.reader).take(end - self.hashed_len);
+            self.hashed_len += std::io::copy(&mut unhashed, &mut self.hasher)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for HashingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        let pos = state.pos;
+        if state.hashed_len < pos {
+            // Bytes which were seeked past are hashed first, so the hash is in order.
+            state.hash_to(pos)?;
+            state.reader.seek(SeekFrom::Start(pos))?;
+        }
+        let len = state.reader.read(buf)?;
+        let end = pos + len as u64;
+        if pos <= state.hashed_len && state.hashed_len < end {
+            let start = (state.hashed_len - pos) as usize;
+            state.hasher.update(&buf[start..len]);
+            state.hashed_len = end;
+        }
+        state.pos = end;
+        Ok(len)
+    }
+}
+
+impl Seek for HashingReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let mut state = self.0.lock().unwrap();
+        state.pos = state.reader.seek(pos)?;
+        Ok(state.pos)
+    }
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.to_string_lossy())?;
+        for entry in &self.entries {
+            write!(f, "{}{}", ARCHIVE_SEPARATOR, entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which files to search for logs, from `Options::include`, `Options::exclude` and `Options::max_depth`.
+pub(crate) struct FileFilter {
+    /// `None` to include every file.
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    max_depth: Option<usize>,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String], max_depth: Option<usize>) -> Result<Self> {
+        let glob_set = |globs: &[String]| -> Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(Glob::new(glob)?);
+            }
+            Ok(builder.build()?)
+        };
+        Ok(Self {
+            include: (!include.is_empty())
+                .then(|| glob_set(include))
+                .transpose()?,
+            exclude: glob_set(exclude)?,
+            max_depth,
+        })
+    }
+
+    fn is_excluded(&self, name: &str) -> bool {
+        self.exclude.is_match(name)
+    }
+
+    fn includes(&self, name: &str) -> bool {
+        !self.is_excluded(name)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(name))
+    }
+}
+
+/// Find the files to search for logs at `path`, which is either a file, or a directory which is searched recursively.
+/// Archives are expanded into the files within them. The files are sorted, so the order doesn't depend on the
+/// file system.
+pub(crate) fn find_sources(path: &Path, filter: &FileFilter) -> Result<Vec<LogSource>> {
+    let paths = find_files(path, filter)?;
+    Ok(expand_files(root_dir(path), paths, filter))
+}
+
+/// The directory which paths are relative to when parsing `path`. Either `path` itself if it's a directory, or the
+/// directory containing it.
+pub(crate) fn root_dir(path: &Path) -> &Path {
+    if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+/// The files on disk at `path`, which is either a file, or a directory which is searched recursively.
+pub(crate) fn find_files(path: &Path, filter: &FileFilter) -> Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        let mut paths = vec![];
+        find_files_in(path, path, filter, 0, &mut paths)?;
+        Ok(paths)
+    } else {
+        Ok(vec![path.to_path_buf()])
+    }
+}
+
+/// The files to search for logs in the files at `paths`, which are relative to `root`. Archives are expanded into the
+/// files within them.
+pub(crate) fn expand_files(
+    root: &Path,
+    paths: Vec<PathBuf>,
+    filter: &FileFilter,
+) -> Vec<LogSource> {
+    let mut sources = vec![];
+    for path in paths {
+        match expand_source(LogSource::file(path.clone())) {
+            Ok(expanded) => sources.extend(expanded),
+            Err(err) => log::error!("Error reading archive {:?}: {}", path, err),
+        }
+    }
+    filter_sources(root, sources, filter)
+}
+
+/// The files to search for logs in logs read from a stream, which may be an archive.
+pub(crate) fn expand_stream(source: LogSource, filter: &FileFilter) -> Result<Vec<LogSource>> {
+    let sources = expand_source(source)?;
+    Ok(filter_sources(Path::new(""), sources, filter))
+}
+
+/// Remove the sources which are filtered out, or which can't be logs, and sort the rest.
+fn filter_sources(root: &Path, mut sources: Vec<LogSource>, filter: &FileFilter) -> Vec<LogSource> {
+    sources.retain(|source| {
+        if !filter.includes(&source.filter_name(root)) {
+            log::trace!("Skipping file '{}', which is filtered out", source);
+            return false;
+        }
+        match source.may_be_logs() {
+            Ok(true) => true,
+            Ok(false) => {
+                log::debug!("Skipping binary file '{}'", source);
+                false
+            }
+            Err(err) => {
+                log::error!("Error reading file '{}': {}", source, err);
+                false
+            }
+        }
+    });
+    sources.sort();
+    sources
+}
+
+/// Recursively find the files in `dir`, which is `depth` directories below `root`. Directories which are excluded by
+/// the filter, or are deeper than its max depth, are not searched. Symbolic links to directories are not followed.
+fn find_files_in(
+    root: &Path,
+    dir: &Path,
+    filter: &FileFilter,
+    depth: usize,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            let name = path_name(path.strip_prefix(root).unwrap_or(&path));
+            if filter.is_excluded(&name) {
+                log::trace!("Skipping directory {:?}, which is excluded", path);
+            } else if filter.max_depth.is_none_or(|max_depth| depth < max_depth) {
+                find_files_in(root, &path, filter, depth + 1, files)?;
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The path with '/' separators on every platform, so globs behave the same everywhere.
+fn path_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn expand_source(source: LogSource) -> Result<Vec<LogSource>> {
+    let Some(kind) = ArchiveKind::from_name(source.file_name()) else {
+        return Ok(vec![source]);
+    };
+    log::debug!("Searching for log files in archive {}", source);
+    let archive = ArchiveIndex::new(source.open()?, kind, BINARY_CHECK_BYTES)?;
+    let names: Vec<String> = archive.names().map(str::to_string).collect();
+    let archive = Arc::new(OnceLock::from(archive));
+    let mut sources = vec![];
+    for name in names {
+        sources.extend(expand_source(source.child(name, archive.clone()))?);
+    }
+    Ok(sources)
+}
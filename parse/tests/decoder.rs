@@ -0,0 +1,127 @@
+use std::{io::Cursor, path::PathBuf};
+
+use lumberjack_parse::{
+    data::Level,
+    decoder::{self, Decoder},
+};
+
+/// Decode the given bytes to the end, returning the number of lines and the number of skipped regions.
+fn decode(data: &[u8]) -> Option<(usize, usize)> {
+    let decoder = Decoder::new(Cursor::new(data)).ok()?;
+    let (mut line_count, mut skipped_count) = (0, 0);
+    for line in decoder {
+        match line {
+            Ok(_) => line_count += 1,
+            Err(lumberjack_parse::Error::CorruptBinaryLogs(_, _, _)) => skipped_count += 1,
+            Err(err) => panic!("Unexpected error {}", err),
+        }
+    }
+    Some((line_count, skipped_count))
+}
+
+fn binary_logs_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("test_data/binary_logs")
+}
+
+fn binary_logs() -> Vec<Vec<u8>> {
+    std::fs::read_dir(binary_logs_path())
+        .unwrap()
+        .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+        .collect()
+}
+
+#[test]
+fn decode_valid() {
+    for data in binary_logs() {
+        let (line_count, skipped_count) = decode(&data).unwrap();
+        assert!(line_count > 0);
+        assert_eq!(skipped_count, 0);
+    }
+}
+
+#[test]
+fn decode_truncated() {
+    for data in binary_logs() {
+        for len in (0..data.len()).step_by(31) {
+            decode(&data[..len]);
+        }
+    }
+}
+
+#[test]
+fn decode_corrupted() {
+    for data in binary_logs() {
+        for i in (6..data.len()).step_by(37) {
+            for byte in [0x00, 0x7F, 0x80, 0xFF] {
+                let mut data = data.clone();
+                data[i] = byte;
+                decode(&data);
+            }
+        }
+    }
+}
+
+#[test]
+fn decode_invalid_header() {
+    assert!(decode(&[]).is_none());
+    assert!(decode(&[0xCF, 0xB2, 0xAB, 0x1B, 0x01]).is_none());
+    // Unsupported pointer size
+    assert!(decode(&[0xCF, 0xB2, 0xAB, 0x1B, 0x01, 0x03, 0x00]).is_none());
+    // Start time varint is too long
+    assert!(decode(&[0xCF, 0xB2, 0xAB, 0x1B, 0x01, 0x08, 0xFF, 0xFF, 0xFF, 0xFF]).is_none());
+}
+
+#[test]
+fn read_entries() {
+    let decoder = Decoder::open(binary_logs_path().join("cbl_info_1722271848300.cbllog")).unwrap();
+
+    assert_eq!(decoder.pointer_size(), 8);
+    assert_eq!(
+        decoder.start_time().format("%Y-%m-%d").to_string(),
+        "2024-07-29"
+    );
+    let metadata = decoder.metadata();
+    assert!(metadata.contains(&("serialNo".to_string(), "8".to_string())));
+    assert!(metadata.contains(&("fileMaxSize".to_string(), "16384".to_string())));
+
+    let entries: Vec<_> = decoder.map(Result::unwrap).collect();
+    // Reading the metadata doesn't consume the header entry
+    assert!(entries[0].message.starts_with("---- serialNo=8,"));
+    assert!(entries[0].domain.is_empty());
+
+    let entry = &entries[3];
+    assert_eq!(entry.domain, "Sync");
+    assert_eq!(entry.level, Level::Info);
+    assert_eq!(
+        entry.object.as_deref(),
+        Some("/apiTest/C4RemoteRepl#1299/Repl#1300/Inserter#1308/")
+    );
+    assert!(entry.message.starts_with("Coll=1 Inserted 100 revs in "));
+    assert!(entries.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+}
+
+#[test]
+fn merge_entries() {
+    let paths = || {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(binary_logs_path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+    };
+    let decoders = || paths().into_iter().map(|path| Decoder::open(path).unwrap());
+
+    let mut expected: Vec<_> = decoders().flatten().map(Result::unwrap).collect();
+    let merged: Vec<_> = decoder::merge(decoders()).map(Result::unwrap).collect();
+
+    assert!(merged.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    assert!(merged.iter().any(|entry| entry.level == Level::Debug));
+    assert!(merged.iter().any(|entry| entry.level == Level::Error));
+    // A stable sort of the entries gives the same order as merging.
+    expected.sort_by_key(|entry| entry.timestamp);
+    assert_eq!(merged, expected);
+}
@@ -0,0 +1,169 @@
+use std::{io::Cursor, path::PathBuf};
+
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use lumberjack_parse::{
+    data::Level,
+    decoder::{Decoder, Entry},
+    encoder::{Arg, Encoder},
+};
+
+fn start_time() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 7, 29)
+        .unwrap()
+        .and_hms_opt(16, 50, 12)
+        .unwrap()
+}
+
+fn decode(data: Vec<u8>) -> Vec<Entry> {
+    Decoder::new(Cursor::new(data))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+}
+
+/// Decoding, re-encoding and decoding again gives the same entries.
+#[test]
+fn round_trip_binary_logs() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("test_data/binary_logs");
+
+    for path in std::fs::read_dir(dir).unwrap() {
+        let decoder = Decoder::open(path.unwrap().path()).unwrap();
+        let pointer_size = decoder.pointer_size();
+        let start_time = decoder.start_time();
+        let metadata = decoder.metadata().to_vec();
+        let entries: Vec<Entry> = decoder.map(Result::unwrap).collect();
+
+        let mut encoder = Encoder::new(vec![], start_time, pointer_size).unwrap();
+        for entry in &entries {
+            encoder.write_entry(entry).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+
+        let decoder = Decoder::new(Cursor::new(data)).unwrap();
+        assert_eq!(decoder.pointer_size(), pointer_size);
+        assert_eq!(decoder.start_time(), start_time);
+        assert_eq!(decoder.metadata(), metadata.as_slice());
+        let decoded: Vec<Entry> = decoder.map(Result::unwrap).collect();
+        assert_eq!(decoded, entries);
+    }
+}
+
+#[test]
+fn formatted_args() {
+    let timestamp = start_time() + TimeDelta::microseconds(1_500_123);
+    let mut encoder = Encoder::new(vec![], start_time(), 4).unwrap();
+    encoder
+        .write_formatted(
+            timestamp,
+            Level::Warning,
+            "Sync",
+            Some("/Repl#1/"),
+            "%d %u %x %.2f %s %-s %p 100%%",
+            &[
+                Arg::Int(-42),
+                Arg::UInt(7),
+                Arg::Hex(255),
+                Arg::Float(1.5),
+                Arg::String("raw".into()),
+                Arg::Token("tokenized".into()),
+                Arg::Pointer(0xBEEF),
+            ],
+        )
+        .unwrap();
+    let entries = decode(encoder.finish().unwrap());
+
+    assert_eq!(
+        entries,
+        vec![Entry {
+            timestamp,
+            level: Level::Warning,
+            domain: "Sync".into(),
+            object: Some("/Repl#1/".into()),
+            message: "-42 7 ff 1.5 raw tokenized 0x00beef 100%".into(),
+        }]
+    );
+}
+
+/// Repeated domains, format strings and objects are only written once.
+#[test]
+fn interning() {
+    let write = |count: usize| {
+        let mut encoder = Encoder::new(vec![], start_time(), 8).unwrap();
+        encoder
+            .write_metadata(&[("serialNo".into(), "1".into())])
+            .unwrap();
+        for i in 0..count {
+            encoder
+                .write_formatted(
+                    start_time() + TimeDelta::seconds(i as i64),
+                    Level::Info,
+                    "DB",
+                    Some("/DB#101/"),
+                    "Opening database %-s",
+                    &[Arg::Token("db.cblite2".into())],
+                )
+                .unwrap();
+        }
+        encoder.finish().unwrap()
+    };
+
+    let one = write(1);
+    let two = write(2);
+    let three = write(3);
+    // Each entry after the first is just the timestamp, level and the IDs of the domain, object, format and parameter.
+    assert_eq!(three.len() - two.len(), 8);
+    assert!(two.len() - one.len() < 10);
+
+    let entries = decode(three);
+    assert_eq!(entries.len(), 4);
+    assert!(entries[1..]
+        .iter()
+        .all(|entry| entry.message == "Opening database db.cblite2"));
+    assert_eq!(entries[3].timestamp, start_time() + TimeDelta::seconds(2));
+}
+
+#[test]
+fn invalid_entries() {
+    let mut encoder = Encoder::new(vec![], start_time(), 4).unwrap();
+    let entry = Entry {
+        timestamp: start_time() + TimeDelta::seconds(1),
+        level: Level::Info,
+        domain: "DB".into(),
+        object: None,
+        message: "message".into(),
+    };
+    encoder.write_entry(&entry).unwrap();
+
+    // Out of order
+    let earlier = Entry {
+        timestamp: start_time(),
+        ..entry.clone()
+    };
+    assert!(encoder.write_entry(&earlier).is_err());
+
+    let with_null = Entry {
+        domain: "D\0B".into(),
+        ..entry.clone()
+    };
+    assert!(encoder.write_entry(&with_null).is_err());
+
+    assert!(encoder
+        .write_formatted(
+            entry.timestamp,
+            Level::Info,
+            "DB",
+            None,
+            "%p",
+            &[Arg::Pointer(u64::MAX)]
+        )
+        .is_err());
+
+    // The failed entries weren't written
+    let entries = decode(encoder.finish().unwrap());
+    assert_eq!(entries, vec![entry]);
+
+    assert!(Encoder::new(vec![], start_time(), 2).is_err());
+}
@@ -1,36 +1,63 @@
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Once,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 fn test_zero_errors(test_file_path: &str) {
+    // Unknown events would be stored rather than counted as errors, so skip them to check every line is understood.
+    let options = lumberjack_parse::Options {
+        skip_unknown: true,
+        ..Default::default()
+    };
+
+    let report = parse_test_data(test_file_path, options);
+    assert!(report.failed_files.is_empty());
+    assert_eq!(report.error_count(), 0);
+}
+
+/// Parse the given path in the `test_data` directory into a temporary database, returning the report.
+fn parse_test_data(
+    test_file_path: &str,
+    options: lumberjack_parse::Options,
+) -> lumberjack_parse::ParseReport {
+    parse_path(&test_data_path(test_file_path), options)
+}
+
+/// Parse the logs at the given path into a temporary database, returning the report.
+fn parse_path(
+    logs_path: &Path,
+    options: lumberjack_parse::Options,
+) -> lumberjack_parse::ParseReport {
     static INIT_LOGGING: Once = Once::new();
 
     INIT_LOGGING.call_once(|| {
         env_logger::builder().init();
     });
 
-    let logs_path = PathBuf::from_str(env!("CARGO_MANIFEST_DIR"))
+    let db_path = temp_dir().join("output.sqlite");
+
+    lumberjack_parse::parse(logs_path, &db_path, options).expect("Failed to parse")
+}
+
+fn test_data_path(test_file_path: &str) -> PathBuf {
+    PathBuf::from_str(env!("CARGO_MANIFEST_DIR"))
         .unwrap()
         .parent()
         .unwrap()
-        .join(format!("test_data/{}", test_file_path));
+        .join(format!("test_data/{}", test_file_path))
+}
 
+/// Create a new, empty, temporary directory.
+fn temp_dir() -> PathBuf {
     let temp_dir = std::env::temp_dir()
         .join("lumberjack_test_parse/")
         .join(format!("{}/", epoch_id()));
 
     std::fs::remove_dir_all(&temp_dir).ok();
     std::fs::create_dir_all(&temp_dir).ok();
-
-    let db_path = temp_dir.join("output.sqlite");
-
-    let err_count =
-        lumberjack_parse::parse(&logs_path, &db_path, lumberjack_parse::Options::default())
-            .expect("Failed to parse");
-    assert_eq!(err_count, 0);
+    temp_dir
 }
 
 #[test]
@@ -43,6 +70,99 @@ fn binary_logs() {
     test_zero_errors("binary_logs")
 }
 
+#[test]
+fn parse_report() {
+    let report = parse_test_data("binary_logs", lumberjack_parse::Options::default());
+
+    assert_eq!(report.files.len(), 6);
+    assert!(report.failed_files.is_empty());
+    for file in &report.files {
+        assert_eq!(file.version, "3.2.0");
+        assert_eq!(file.platform, "litecore_cpptest_binary");
+        assert_eq!(
+            file.line_count,
+            file.parsed_count + file.error_count + file.ignored_count
+        );
+        assert_eq!(
+            file.errors.values().sum::<u64>(),
+            file.error_count + file.ignored_count
+        );
+    }
+
+    assert_eq!(report.line_count(), 206);
+    assert_eq!(report.parsed_count(), 139);
+    let errors = report.errors();
+    assert_eq!(errors.get("NoDomain"), Some(&17));
+    assert_eq!(errors.get("IgnoredEvent"), Some(&50));
+    assert!(report.duration >= report.files[0].duration);
+}
+
+const BINARY_LOG: &str = "binary_logs/cbl_info_1722271846216.cbllog";
+
+#[test]
+fn recover_truncated_binary_logs() {
+    let data = std::fs::read(test_data_path(BINARY_LOG)).unwrap();
+    let log_path = temp_dir().join("cbl_info_1722271846216.cbllog");
+    std::fs::write(&log_path, &data[..12_000]).unwrap();
+
+    let report = parse_path(
+        &log_path,
+        lumberjack_parse::Options {
+            recover: true,
+            ..Default::default()
+        },
+    );
+    assert!(report.failed_files.is_empty());
+    let file = &report.files[0];
+    assert!(file.parsed_count > 0);
+    // The incomplete entry at the end of the file is skipped.
+    assert_eq!(file.skipped_regions.len(), 1);
+    let region = &file.skipped_regions[0];
+    assert!(region.offset < 12_000);
+    assert_eq!(region.offset + region.length, 12_000);
+
+    // Without recovery, the file fails, but the lines decoded before the truncation are kept.
+    let db_path = log_path.with_file_name("output.sqlite");
+    let report =
+        lumberjack_parse::parse(&log_path, &db_path, lumberjack_parse::Options::default()).unwrap();
+    assert!(report.files.is_empty());
+    assert_eq!(report.failed_files.len(), 1);
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let line_count: u64 = conn
+        .query_row("SELECT COUNT(*) FROM lines", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(line_count, file.parsed_count);
+}
+
+#[test]
+fn recover_corrupt_binary_logs() {
+    let mut data = std::fs::read(test_data_path(BINARY_LOG)).unwrap();
+    let original_len = data.len() as u64;
+    data[15_000..15_008].fill(0xFF);
+    let log_path = temp_dir().join("cbl_info_1722271846216.cbllog");
+    std::fs::write(&log_path, &data).unwrap();
+
+    let report = parse_path(
+        &log_path,
+        lumberjack_parse::Options {
+            recover: true,
+            ..Default::default()
+        },
+    );
+    assert!(report.failed_files.is_empty());
+    let file = &report.files[0];
+    assert!(!file.skipped_regions.is_empty());
+    let first = &file.skipped_regions[0];
+    assert!(first.offset >= 15_000);
+    for region in &file.skipped_regions {
+        assert!(region.offset + region.length <= original_len);
+    }
+    // Decoding resumed after the corruption, so entries after it were read.
+    let last = file.skipped_regions.last().unwrap();
+    assert!(last.offset + last.length < original_len);
+    assert!(file.line_count > 100);
+}
+
 // Returns a unique (within the same process) identifier every time it is called. Useful to run tests in parallel.
 fn epoch_id() -> String {
     use std::sync::atomic::{AtomicU32, Ordering};
@@ -1,9 +1,11 @@
 use std::{
+    path::{Path, PathBuf},
     rc::Rc,
     sync::Once,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use lumberjack_parse::data::FromRow;
 use rusqlite::types::Value;
 
 /// Create logs with the given data, run the parser, and return a connection to the resulting database.
@@ -11,35 +13,74 @@ fn test_with_data<F>(data: &str, f: F)
 where
     F: FnOnce(rusqlite::Connection),
 {
-    static INIT_LOGGING: Once = Once::new();
-
-    INIT_LOGGING.call_once(|| {
-        env_logger::builder().init();
-    });
+    test_with_files(
+        &[("test.cbllog", data)],
+        lumberjack_parse::Options::default(),
+        f,
+    );
+}
 
+/// Create a directory of log files with the given names and data, run the parser over the directory, and return a
+/// connection to the resulting database.
+fn test_with_files<F>(files: &[(&str, &str)], options: lumberjack_parse::Options, f: F)
+where
+    F: FnOnce(rusqlite::Connection),
+{
     let temp_dir = std::env::temp_dir()
         .join("lumberjack_test_queries/")
         .join(format!("{}/", epoch_id()));
 
     std::fs::remove_dir_all(&temp_dir).ok();
-    std::fs::create_dir_all(&temp_dir).ok();
-    let logs_path = temp_dir.join("test.cbllog");
+    let logs_path = temp_dir.join("logs");
+    std::fs::create_dir_all(&logs_path).ok();
 
-    std::fs::write(&logs_path, data).unwrap();
+    for (name, data) in files {
+        std::fs::write(logs_path.join(name), data).unwrap();
+    }
 
-    let db_path = temp_dir.join("output.sqlite");
+    let logs_path = if files.len() == 1 {
+        logs_path.join(files[0].0)
+    } else {
+        logs_path
+    };
 
-    lumberjack_parse::parse(&logs_path, &db_path, lumberjack_parse::Options::default())
-        .expect("Parsing failed!");
+    test_with_path(&logs_path, &temp_dir, options, f);
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+/// Run the parser over the logs at the given path, and return a connection to the resulting database.
+/// The database is written to `out_dir`.
+fn test_with_path<F>(logs_path: &Path, out_dir: &Path, options: lumberjack_parse::Options, f: F)
+where
+    F: FnOnce(rusqlite::Connection),
+{
+    static INIT_LOGGING: Once = Once::new();
+
+    INIT_LOGGING.call_once(|| {
+        env_logger::builder().init();
+    });
+
+    std::fs::create_dir_all(out_dir).ok();
+    let db_path = out_dir.join("output.sqlite");
+
+    lumberjack_parse::parse(logs_path, &db_path, options).expect("Parsing failed!");
 
     let conn = rusqlite::Connection::open(db_path).expect("Failed to open database");
     rusqlite::vtab::array::load_module(&conn).expect("Failed to load array module");
 
     f(conn);
+}
 
-    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
-        std::fs::remove_dir_all(&temp_dir).ok();
-    }
+/// The path of the given test data, relative to the `test_data` directory.
+fn test_data_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("test_data")
+        .join(path)
 }
 
 // Returns a unique (within the same process) identifier every time it is called. Useful to run tests in parallel.
@@ -320,3 +361,1741 @@ fn find_failed_pushes() {
         }
     });
 }
+
+/// Parse a file larger than a single parser chunk, and check no lines are lost or misnumbered between chunks.
+#[test]
+fn parse_across_chunks() {
+    const LINE_COUNT: usize = 40_000;
+
+    let mut data = String::from(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+    );
+    for _ in 0..LINE_COUNT {
+        data.push_str("2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n");
+    }
+
+    test_with_data(&data, |conn| {
+        let (count, min_line, max_line): (usize, usize, usize) = conn
+            .query_row(
+                "SELECT COUNT(*), MIN(line_num), MAX(line_num) FROM lines",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+
+        assert_eq!(count, LINE_COUNT);
+        assert_eq!(min_line, 1);
+        assert_eq!(max_line, LINE_COUNT);
+    });
+}
+
+/// Parse a directory of logs from different platforms, and check each file is parsed with its own patterns.
+#[test]
+fn patterns_per_file() {
+    const VANILLA_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Warning Obj=/DB#101/ Opening database\n",
+    );
+    const CPPTEST_DATA: &str = concat!(
+        "2024-07-29T16:50:12.000000 Default Info This is LiteCore EE built from release/3.2 branch\n",
+        "2024-07-29T16:50:13.000000 DB WARNING Obj=/DB#102/ Opening database\n",
+    );
+
+    test_with_files(
+        &[
+            ("vanilla.cbllog", VANILLA_DATA),
+            ("cpptest.cbllog", CPPTEST_DATA),
+        ],
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let mut platforms: Vec<(String, String)> = conn
+                .prepare("SELECT path, platform FROM files")
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .filter_map(Result::ok)
+                .map(|(path, platform): (String, String)| {
+                    let name = path.rsplit('/').next().unwrap().to_string();
+                    (name, platform)
+                })
+                .collect();
+            platforms.sort();
+
+            assert_eq!(
+                platforms,
+                vec![
+                    ("cpptest.cbllog".to_string(), "litecore_cpptest".to_string()),
+                    ("vanilla.cbllog".to_string(), "vanilla".to_string()),
+                ]
+            );
+
+            let db_opens: usize = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM lines WHERE event_type = (SELECT id FROM event_types WHERE name = 'DbOpen')",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(db_opens, 2);
+        },
+    );
+}
+
+/// Check the version line metadata is stored for each file.
+#[test]
+fn version_line_metadata() {
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let session: (u32, String, String, String, u32, String) = conn
+            .query_row(
+                "SELECT line_num, version, platform, os, build, commit_hash FROM sessions",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            session,
+            (
+                0,
+                "3.2.0".to_string(),
+                ".NET".to_string(),
+                "Microsoft Windows 10.0.22621".to_string(),
+                1,
+                "86734653b94fa6db+7f0707145d9db2af".to_string()
+            )
+        );
+    });
+}
+
+/// Check the key/value header of binary logs is stored for each file.
+#[test]
+fn binary_header_metadata() {
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+
+    test_with_path(
+        &test_data_path("binary_logs"),
+        &out_dir,
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let serial_numbers: Vec<String> = conn
+                .prepare(
+                    "
+                SELECT metadata.value
+                FROM metadata JOIN files ON files.id = metadata.file_id
+                WHERE metadata.key = 'serialNo'
+                ORDER BY files.path
+            ",
+                )
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+
+            assert_eq!(serial_numbers.len(), 6);
+            assert!(serial_numbers.contains(&"8".to_string()));
+
+            let commit: String = conn
+                .query_row("SELECT commit_hash FROM sessions LIMIT 1", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(commit, "86734653b94fa6db+7f0707145d9db2af+CHANGES");
+        },
+    );
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+/// Check a header which repeats a key doesn't stop the file being parsed, and the last value is kept.
+#[test]
+fn duplicate_header_metadata() {
+    let data = "---- serialNo=7,logDirectory=/tmp/logs/,serialNo=8 ----
+---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----
+2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database";
+
+    test_with_data(data, |conn| {
+        let metadata: Vec<(String, String)> = conn
+            .prepare("SELECT key, value FROM metadata ORDER BY key")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            metadata,
+            vec![
+                ("logDirectory".to_string(), "/tmp/logs/".to_string()),
+                ("serialNo".to_string(), "8".to_string()),
+            ]
+        );
+
+        let line_count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM lines", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(line_count, 1);
+    });
+}
+
+/// Check a file with several app launches is split into sessions, and each line is linked to the right session.
+#[test]
+fn sessions_within_file() {
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "---- CouchbaseLite/3.2.1 (.NET; Microsoft Windows 10.0.22621) Build/5 LiteCore/3.2.1 (5) Commit/96734653b94fa6db+8f0707145d9db2af ----\n",
+        "2023-12-09T10:00:00.000000 DB Info Obj=/DB#101/ Opening database\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let sessions: Vec<(u32, u32, String)> = conn
+            .prepare("SELECT id, line_num, version FROM sessions ORDER BY line_num")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].1, 0);
+        assert_eq!(sessions[0].2, "3.2.0");
+        assert_eq!(sessions[1].1, 3);
+        assert_eq!(sessions[1].2, "3.2.1");
+
+        let line_sessions: Vec<(u32, u32)> = conn
+            .prepare("SELECT line_num, session_id FROM lines ORDER BY line_num")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        assert_eq!(
+            line_sessions,
+            vec![(1, sessions[0].0), (2, sessions[0].0), (4, sessions[1].0)]
+        );
+    });
+}
+
+/// Check the message and source offset are stored for each line, and can be used to read the surrounding lines.
+#[test]
+fn raw_message_and_context() {
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "Some unparseable line\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Opening database\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let lines: Vec<(u32, String, u64)> = conn
+            .prepare("SELECT line_num, message, source_offset FROM lines ORDER BY line_num")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        let second_offset = (VERSION_LINE.len()
+            + "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n".len()
+            + "Some unparseable line\n".len()) as u64;
+
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    1,
+                    "Obj=/DB#101/ Opening database".to_string(),
+                    VERSION_LINE.len() as u64
+                ),
+                (
+                    3,
+                    "Obj=/DB#102/ Opening database".to_string(),
+                    second_offset
+                ),
+            ]
+        );
+
+        let file_id: u32 = conn
+            .query_row("SELECT id FROM files", [], |row| row.get(0))
+            .unwrap();
+
+        let context = lumberjack_parse::line_context(&conn, file_id, 3, 1).unwrap();
+        assert_eq!(
+            context,
+            vec![
+                (2, "Some unparseable line".to_string()),
+                (
+                    3,
+                    "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Opening database".to_string()
+                ),
+            ]
+        );
+    });
+}
+
+/// Check CBL lines which don't match any known event are stored as `Unknown`, unless skipped.
+#[test]
+fn unknown_events() {
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Warning Obj=/DB#101/ Something nobody has seen before\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#101/ Opening database\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let unknown: Vec<(u32, u32, String, String)> = conn
+            .prepare(
+                "
+                SELECT line_num, level, domain, object_path FROM lines
+                WHERE event_type = (SELECT id FROM event_types WHERE name = 'Unknown')
+            ",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        assert_eq!(
+            unknown,
+            vec![(
+                1,
+                lumberjack_parse::data::Level::Warning as u32,
+                "DB".to_string(),
+                "/DB#101".to_string()
+            )]
+        );
+    });
+
+    test_with_files(
+        &[("test.cbllog", TEST_DATA)],
+        lumberjack_parse::Options {
+            skip_unknown: true,
+            ..Default::default()
+        },
+        |conn| {
+            let count: u32 = conn
+                .query_row("SELECT COUNT(*) FROM lines", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 1);
+        },
+    );
+}
+
+/// Lines logged by the LiteCore tests are ignored, but only when the message starts with them.
+#[test]
+fn tests_only_ignored_lines() {
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 Default Info Committing...\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#101/ Response status: 200\n",
+        "2023-12-08T23:39:25.252743 Sync Info Obj=/Repl#102/ ---- Starting the replicator ---\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let line_nums: Vec<u32> = conn
+            .prepare("SELECT line_num FROM lines ORDER BY line_num")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(line_nums, vec![2, 3]);
+    });
+}
+
+#[test]
+fn parse_errors() {
+    const TEST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Bogus Obj=/DB#101/ Opening database 123\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "Not a CBL line\n",
+    );
+
+    test_with_data(TEST_DATA, |conn| {
+        let errors: Vec<lumberjack_parse::data::ParseError> = conn
+            .prepare("SELECT * FROM parse_errors")
+            .unwrap()
+            .query_map([], lumberjack_parse::data::ParseError::from_row)
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        // Lines which aren't CBL lines are not recorded
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!(error.line_num, 1);
+        assert_eq!(error.error, "NoSuchLevel");
+        assert!(error.signature.contains("{NUMBER}"));
+    });
+}
+
+/// Binary logs are parsed from their decoded fields, so check they produce the same lines as the equivalent text log.
+#[test]
+fn binary_and_text_logs_agree() {
+    const FILE_NAME: &str = "cbl_info_1722271848300.cbllog";
+    let binary_path = test_data_path("binary_logs").join(FILE_NAME);
+
+    let text: Vec<String> = lumberjack_parse::decoder::Decoder::open(&binary_path)
+        .unwrap()
+        .map(|entry| entry.unwrap().to_string())
+        .collect();
+
+    let query_lines = |conn: rusqlite::Connection| -> Vec<Vec<Value>> {
+        conn.prepare(
+            "
+            SELECT line_num, level, timestamp, domain, event_type, event_data, object_path, message
+            FROM lines ORDER BY line_num
+        ",
+        )
+        .unwrap()
+        .query_map([], |row| (0..8).map(|i| row.get(i)).collect())
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+    };
+
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let mut binary_lines = vec![];
+    test_with_path(
+        &binary_path,
+        &out_dir,
+        lumberjack_parse::Options::default(),
+        |conn| binary_lines = query_lines(conn),
+    );
+
+    let mut text_lines = vec![];
+    test_with_files(
+        &[(FILE_NAME, &text.join("\n"))],
+        lumberjack_parse::Options::default(),
+        |conn| text_lines = query_lines(conn),
+    );
+
+    assert!(!binary_lines.is_empty());
+    assert_eq!(binary_lines, text_lines);
+}
+
+/// Files are hashed as they're parsed, which must give the hash of their whole contents, however they're read.
+#[test]
+fn files_are_hashed() {
+    use sha2::{Digest, Sha256};
+
+    let check_hashes = |conn: rusqlite::Connection| {
+        let files: Vec<(String, String)> = conn
+            .prepare("SELECT path, hash FROM files")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(!files.is_empty());
+        for (path, hash) in files {
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(hash, format!("{:x}", Sha256::digest(data)), "{}", path);
+        }
+    };
+
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    test_with_path(
+        &test_data_path("binary_logs"),
+        &out_dir,
+        lumberjack_parse::Options::default(),
+        check_hashes,
+    );
+    test_with_data(
+        concat!(
+            "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+            "2023-12-08T23:39:23.100000 DB Info Obj=/DB#101/ Opening database\n",
+        ),
+        check_hashes,
+    );
+}
+
+/// Lines from separate per-level files are interleaved by timestamp in `seq`, so the lines before an error can be
+/// found across files.
+#[test]
+fn sequence_across_files() {
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    let info_data = format!(
+        "{}{}{}{}",
+        VERSION_LINE,
+        "2023-12-08T23:39:23.100000 DB Info Obj=/DB#101/ First\n",
+        "2023-12-08T23:39:23.300000 DB Info Obj=/DB#101/ Third\n",
+        "2023-12-08T23:39:23.500000 DB Info Obj=/DB#101/ Fifth\n",
+    );
+    let error_data = format!(
+        "{}{}{}",
+        VERSION_LINE,
+        "2023-12-08T23:39:23.200000 DB Error Obj=/DB#101/ Second\n",
+        "2023-12-08T23:39:23.500000 DB Error Obj=/DB#101/ Sixth\n",
+    );
+
+    test_with_files(
+        &[
+            ("cbl_info_1.cbllog", &info_data),
+            ("cbl_error_1.cbllog", &error_data),
+        ],
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let messages: Vec<(u64, String)> = conn
+                .prepare("SELECT seq, message FROM lines WHERE domain = 'DB' ORDER BY seq")
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            let messages: Vec<&str> = messages
+                .iter()
+                .map(|(_, message)| message.strip_prefix("Obj=/DB#101/ ").unwrap())
+                .collect();
+            // Equal timestamps are ordered by file, and cbl_error_1 is sorted before cbl_info_1.
+            assert_eq!(messages, ["First", "Second", "Third", "Sixth", "Fifth"],);
+
+            let seqs: Vec<u64> = conn
+                .prepare("SELECT seq FROM lines ORDER BY seq")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            assert_eq!(seqs, (1..=seqs.len() as u64).collect::<Vec<_>>());
+
+            // The line just before the first error comes from the info file
+            let before: String = conn
+                .query_row(
+                    "
+                    SELECT message FROM lines
+                    WHERE seq = (SELECT seq - 1 FROM lines WHERE level = 0 ORDER BY seq LIMIT 1)",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert!(before.ends_with("First"));
+        },
+    );
+}
+
+/// Rotated files of the same level are chained in the order of the timestamps in their names, and line numbers continue
+/// from one file to the next.
+#[test]
+fn rotated_files() {
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    let first_info = format!(
+        "{}{}{}",
+        VERSION_LINE,
+        "2023-12-08T23:39:23.100000 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:39:23.200000 DB Info Obj=/DB#101/ Opening database\n",
+    );
+    let second_info = format!(
+        "{}{}",
+        VERSION_LINE, "2023-12-08T23:39:24.100000 DB Info Obj=/DB#101/ Opening database\n",
+    );
+    let error = format!(
+        "{}{}",
+        VERSION_LINE, "2023-12-08T23:39:23.150000 DB Error Obj=/DB#101/ Failed\n",
+    );
+
+    test_with_files(
+        &[
+            // The later file sorts first by name, but its timestamp is later
+            ("cbl_info_1702078764000.cbllog", &second_info),
+            ("cbl_info_999999999999.cbllog", &first_info),
+            ("cbl_error_999999999999.cbllog", &error),
+        ],
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let files: Vec<lumberjack_parse::data::File> = conn
+                .prepare("SELECT * FROM files")
+                .unwrap()
+                .query_map([], lumberjack_parse::data::File::from_row)
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            let file = |name: &str| files.iter().find(|file| file.path.contains(name)).unwrap();
+
+            let (first, second, error) = (
+                file("cbl_info_999999999999"),
+                file("cbl_info_1702078764000"),
+                file("cbl_error_999999999999"),
+            );
+            assert_eq!(first.level, Some(lumberjack_parse::data::Level::Info));
+            assert_eq!(error.level, Some(lumberjack_parse::data::Level::Error));
+            assert_eq!(first.predecessor_id, None);
+            assert_eq!(error.predecessor_id, None);
+            assert_eq!(second.predecessor_id, Some(first.id));
+            assert_eq!(first.first_line_num, 0);
+            assert_eq!(error.first_line_num, 0);
+            assert_eq!(second.first_line_num, 3);
+
+            let line_nums: Vec<u32> = conn
+                .prepare("SELECT line_num FROM lines WHERE file_id = $1")
+                .unwrap()
+                .query_map([second.id], |row| row.get(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            assert_eq!(line_nums, [4]);
+
+            let context = lumberjack_parse::line_context(&conn, second.id, 4, 1).unwrap();
+            assert_eq!(
+                context,
+                vec![
+                    (3, VERSION_LINE.trim_end().to_string()),
+                    (
+                        4,
+                        "2023-12-08T23:39:24.100000 DB Info Obj=/DB#101/ Opening database"
+                            .to_string()
+                    ),
+                ]
+            );
+        },
+    );
+}
+
+/// A rotated file with no version line is parsed with the patterns of the file it was rotated from, and files which
+/// aren't logs are still ignored.
+#[test]
+fn rotated_file_without_version_line() {
+    const FIRST_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+    );
+    const SECOND_DATA: &str = concat!(
+        "2023-12-08T23:40:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:40:24.252743 DB Info Obj=/DB#102/ Opening database\n",
+    );
+
+    test_with_files(
+        &[
+            ("cbl_info_1702078763000.cbllog", FIRST_DATA),
+            ("cbl_info_1702078823000.cbllog", SECOND_DATA),
+            ("notes.txt", "Not a log file\n"),
+        ],
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let files: Vec<lumberjack_parse::data::File> = conn
+                .prepare("SELECT * FROM files ORDER BY id")
+                .unwrap()
+                .query_map([], lumberjack_parse::data::File::from_row)
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+
+            assert_eq!(files.len(), 2);
+            assert_eq!(
+                files[0].patterns_source,
+                lumberjack_parse::data::PatternsSource::VersionLine
+            );
+            assert_eq!(
+                files[1].patterns_source,
+                lumberjack_parse::data::PatternsSource::Sibling
+            );
+            assert_eq!(files[1].version, "3.2.0");
+            assert_eq!(files[1].platform, "vanilla");
+
+            let count: u32 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM lines WHERE file_id = $1",
+                    [files[1].id],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, 2);
+        },
+    );
+}
+
+/// The version and platform can be forced for logs which have no version line at all.
+#[test]
+fn forced_patterns() {
+    const TEST_DATA: &str = concat!(
+        "2023-12-08T23:40:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:40:24.252743 DB Info Obj=/DB#102/ Opening database\n",
+    );
+    let options = lumberjack_parse::Options {
+        cbl_version: Some(semver::Version::new(3, 2, 0)),
+        platform: Some("vanilla".to_string()),
+        ..Default::default()
+    };
+
+    test_with_files(&[("test.cbllog", TEST_DATA)], options, |conn| {
+        let file: lumberjack_parse::data::File = conn
+            .query_row(
+                "SELECT * FROM files",
+                [],
+                lumberjack_parse::data::File::from_row,
+            )
+            .unwrap();
+        assert_eq!(
+            file.patterns_source,
+            lumberjack_parse::data::PatternsSource::Override
+        );
+        assert_eq!(file.version, "3.2.0");
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM lines", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    });
+}
+
+/// Log files are read from within archives, including archives nested in other archives, and their path within the
+/// archive is stored.
+#[test]
+fn logs_in_archives() {
+    use std::io::Write;
+
+    const TEXT_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Opening database\n",
+    );
+    let binary_data =
+        std::fs::read(test_data_path("binary_logs").join("cbl_info_1722271848300.cbllog")).unwrap();
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    zip.start_file(
+        "binary/cbl_info_1722271848300.cbllog",
+        zip::write::SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.write_all(&binary_data).unwrap();
+    let zip_data = zip.finish().unwrap().into_inner();
+
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+        vec![],
+        flate2::Compression::default(),
+    ));
+    for (path, data) in [
+        (
+            "bundle/text/cbl_info_1702078763000.txt",
+            TEXT_DATA.as_bytes(),
+        ),
+        ("bundle/nested.zip", &zip_data),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, path, data).unwrap();
+    }
+    let tar_data = tar.into_inner().unwrap().finish().unwrap();
+
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let archive_path = out_dir.join("bundle.tar.gz");
+    std::fs::write(&archive_path, tar_data).unwrap();
+
+    test_with_path(
+        &archive_path,
+        &out_dir,
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let files: Vec<lumberjack_parse::data::File> = conn
+                .prepare("SELECT * FROM files ORDER BY archive_path")
+                .unwrap()
+                .query_map([], lumberjack_parse::data::File::from_row)
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+
+            assert_eq!(files.len(), 2);
+            assert!(files
+                .iter()
+                .all(|file| file.path == archive_path.to_string_lossy()));
+            assert_eq!(
+                files[0].archive_path.as_deref(),
+                Some("bundle/nested.zip!/binary/cbl_info_1722271848300.cbllog")
+            );
+            assert_eq!(
+                files[1].archive_path.as_deref(),
+                Some("bundle/text/cbl_info_1702078763000.txt")
+            );
+
+            for file in &files {
+                let count: u32 = conn
+                    .query_row(
+                        "SELECT COUNT(*) FROM lines WHERE file_id = $1",
+                        [file.id],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                assert!(count > 0);
+            }
+
+            let context = lumberjack_parse::line_context(&conn, files[1].id, 2, 0).unwrap();
+            assert_eq!(
+                context,
+                vec![(
+                    2,
+                    "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Opening database".to_string()
+                )]
+            );
+        },
+    );
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+/// Files within archives are read from the archive without extracting them, seeking within files which are larger than
+/// the distance between the points the decompressed data can be read from.
+#[test]
+fn large_logs_in_archives() {
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    const LINE_COUNT: u32 = 10_000;
+    // Long lines, so the file is several megabytes without taking long to parse.
+    let line = |num: u32| {
+        format!(
+            "2023-12-08T23:39:23.252743 DB Info Obj=/DB#{}/ Opening database {}",
+            num,
+            "x".repeat(500)
+        )
+    };
+    let large_data = std::iter::once(VERSION_LINE.to_string())
+        .chain((1..=LINE_COUNT).map(|num| line(num) + "\n"))
+        .collect::<String>();
+    let small_data = format!(
+        "{}2023-12-08T23:39:24.252743 DB Info Obj=/DB#1/ Opening database\n",
+        VERSION_LINE
+    );
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    zip.start_file(
+        "large/cbl_info_1702078763000.txt",
+        zip::write::SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.write_all(large_data.as_bytes()).unwrap();
+    let zip_data = zip.finish().unwrap().into_inner();
+
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+        vec![],
+        flate2::Compression::default(),
+    ));
+    for (path, data) in [
+        ("large/cbl_info_1702078763000.txt", large_data.as_bytes()),
+        ("nested.zip", &zip_data),
+        ("small/cbl_info_1702078764000.txt", small_data.as_bytes()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, path, data).unwrap();
+    }
+    let tar_data = tar.into_inner().unwrap().finish().unwrap();
+
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let archive_path = out_dir.join("bundle.tar.gz");
+    std::fs::write(&archive_path, tar_data).unwrap();
+
+    test_with_path(
+        &archive_path,
+        &out_dir,
+        lumberjack_parse::Options::default(),
+        |conn| {
+            let files: Vec<lumberjack_parse::data::File> = conn
+                .prepare("SELECT * FROM files ORDER BY archive_path")
+                .unwrap()
+                .query_map([], lumberjack_parse::data::File::from_row)
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            let archive_paths: Vec<_> = files
+                .iter()
+                .map(|file| file.archive_path.as_deref().unwrap())
+                .collect();
+            assert_eq!(
+                archive_paths,
+                vec![
+                    "large/cbl_info_1702078763000.txt",
+                    "nested.zip!/large/cbl_info_1702078763000.txt",
+                    "small/cbl_info_1702078764000.txt",
+                ]
+            );
+
+            for file in &files[..2] {
+                assert_eq!(
+                    file.hash,
+                    format!("{:x}", Sha256::digest(large_data.as_bytes()))
+                );
+                for num in [LINE_COUNT, 1, LINE_COUNT / 2] {
+                    let context = lumberjack_parse::line_context(&conn, file.id, num, 0).unwrap();
+                    assert_eq!(context, vec![(num, line(num))]);
+                }
+            }
+            assert_eq!(
+                files[2].hash,
+                format!("{:x}", Sha256::digest(small_data.as_bytes()))
+            );
+        },
+    );
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn recursive_discovery() {
+    const TEXT_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+    );
+
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let logs_path = temp_dir.join("logs");
+    for (path, data) in [
+        ("cbl_info_1702078763000.txt", TEXT_DATA.as_bytes()),
+        ("app/cbl_info_1702078764000.txt", TEXT_DATA.as_bytes()),
+        ("app/old/cbl_info_1702078765000.txt", TEXT_DATA.as_bytes()),
+        (
+            "app/crash.dmp",
+            &[0x4d, 0x44, 0x4d, 0x50, 0x00, 0x00, 0xa7, 0x93],
+        ),
+        ("tmp/cbl_info_1702078766000.txt", TEXT_DATA.as_bytes()),
+    ] {
+        let path = logs_path.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+
+    let relative_paths = |name: &str, options: lumberjack_parse::Options| {
+        let mut paths = vec![];
+        test_with_path(&logs_path, &temp_dir.join(name), options, |conn| {
+            paths = conn
+                .prepare("SELECT relative_path FROM files ORDER BY relative_path")
+                .unwrap()
+                .query_map([], |row| row.get::<_, String>(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+        });
+        paths
+    };
+
+    assert_eq!(
+        relative_paths("all", lumberjack_parse::Options::default()),
+        vec![
+            "app/cbl_info_1702078764000.txt",
+            "app/old/cbl_info_1702078765000.txt",
+            "cbl_info_1702078763000.txt",
+            "tmp/cbl_info_1702078766000.txt",
+        ]
+    );
+    assert_eq!(
+        relative_paths(
+            "max_depth",
+            lumberjack_parse::Options {
+                max_depth: Some(1),
+                ..Default::default()
+            }
+        ),
+        vec![
+            "app/cbl_info_1702078764000.txt",
+            "cbl_info_1702078763000.txt",
+            "tmp/cbl_info_1702078766000.txt",
+        ]
+    );
+    assert_eq!(
+        relative_paths(
+            "filtered",
+            lumberjack_parse::Options {
+                include: vec!["app/**".to_string(), "tmp/**".to_string()],
+                exclude: vec!["app/old".to_string()],
+                ..Default::default()
+            }
+        ),
+        vec![
+            "app/cbl_info_1702078764000.txt",
+            "tmp/cbl_info_1702078766000.txt",
+        ]
+    );
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn parse_from_reader() {
+    const TEXT_DATA: &str = concat!(
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n",
+        "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+        "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Opening database\n",
+    );
+    let binary_data =
+        std::fs::read(test_data_path("binary_logs").join("cbl_info_1722271848300.cbllog")).unwrap();
+
+    let out_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    for (label, data, level) in [
+        ("stdin", TEXT_DATA.as_bytes().to_vec(), None),
+        (
+            "cbl_info_1722271848300.cbllog",
+            binary_data,
+            Some(lumberjack_parse::data::Level::Info),
+        ),
+    ] {
+        let db_path = out_dir.join(format!("{}.sqlite", label));
+        let report = lumberjack_parse::parse_reader(
+            std::io::Cursor::new(data),
+            label,
+            &db_path,
+            lumberjack_parse::Options::default(),
+        )
+        .expect("Parsing failed!");
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].path, label);
+
+        let conn = rusqlite::Connection::open(db_path).expect("Failed to open database");
+        let file = conn
+            .query_row(
+                "SELECT * FROM files",
+                [],
+                lumberjack_parse::data::File::from_row,
+            )
+            .unwrap();
+        assert_eq!(file.path, label);
+        assert_eq!(file.relative_path, label);
+        assert_eq!(file.level, level);
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM lines", [], |row| row.get(0))
+            .unwrap();
+        assert!(count > 0);
+    }
+
+    // Text logs whose version line is past the start of the stream can't be parsed without forcing the patterns, as
+    // the stream can't be read again to find it.
+    let mut late_version = String::new();
+    for _ in 0..10_000 {
+        late_version.push_str("2023-12-08T23:39:22.252743 DB Info Obj=/DB#100/ Opening database\n");
+    }
+    late_version.push_str(TEXT_DATA);
+    let db_path = out_dir.join("late_version.sqlite");
+    assert!(matches!(
+        lumberjack_parse::parse_reader(
+            std::io::Cursor::new(late_version.clone().into_bytes()),
+            "stdin",
+            &db_path,
+            lumberjack_parse::Options::default(),
+        ),
+        Err(lumberjack_parse::Error::NotLogs(_))
+    ));
+    let report = lumberjack_parse::parse_reader(
+        std::io::Cursor::new(late_version.into_bytes()),
+        "stdin",
+        &db_path,
+        lumberjack_parse::Options {
+            cbl_version: Some(semver::Version::new(3, 2, 0)),
+            platform: Some("vanilla".to_string()),
+            ..Default::default()
+        },
+    )
+    .expect("Parsing failed!");
+    assert_eq!(report.files[0].line_count, 10_003);
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn follow_growing_logs() {
+    use std::io::Write;
+
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    let binary_data =
+        std::fs::read(test_data_path("binary_logs").join("cbl_info_1722271848300.cbllog")).unwrap();
+
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let logs_path = temp_dir.join("logs");
+    std::fs::create_dir_all(logs_path.join("binary")).unwrap();
+    let text_path = logs_path.join("cbl_info_1702078763000.txt");
+    let binary_path = logs_path.join("binary/cbl_info_1722271848300.cbllog");
+    let append = |path: &Path, data: &[u8]| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(data)
+            .unwrap();
+    };
+
+    // The logs are written part way through a line and a binary entry.
+    append(
+        &text_path,
+        format!(
+            "{}{}{}",
+            VERSION_LINE,
+            "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+            "2023-12-08T23:39:24.252743 DB Info Obj=/DB#102/ Open",
+        )
+        .as_bytes(),
+    );
+    let (binary_start, binary_end) = binary_data.split_at(binary_data.len() / 2);
+    append(&binary_path, binary_start);
+
+    let line_count = |conn: &rusqlite::Connection, path: &str| -> u32 {
+        conn.query_row(
+            "
+            SELECT COUNT(*) FROM lines
+            JOIN files ON files.id = lines.file_id
+            WHERE files.relative_path = $1",
+            [path],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    let db_path = temp_dir.join("followed.sqlite");
+    let mut follower =
+        lumberjack_parse::Follower::new(&logs_path, &db_path, lumberjack_parse::Options::default())
+            .unwrap();
+    let (report, first_count) = follower.poll().unwrap();
+    assert!(report.failed_files.is_empty());
+    assert!(first_count > 0);
+    {
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        assert_eq!(line_count(&conn, "cbl_info_1702078763000.txt"), 1);
+    }
+
+    // The rest of the logs are written, and the text log is rotated.
+    append(&text_path, "ing database\n".as_bytes());
+    append(&binary_path, binary_end);
+    append(
+        &logs_path.join("cbl_info_1702078765000.txt"),
+        format!(
+            "{}{}",
+            VERSION_LINE, "2023-12-08T23:39:25.252743 DB Info Obj=/DB#103/ Opening database\n",
+        )
+        .as_bytes(),
+    );
+    let (report, second_count) = follower.poll().unwrap();
+    assert!(report.failed_files.is_empty());
+    assert!(second_count > 0);
+    assert_eq!(follower.poll().unwrap().1, 0);
+
+    // Following the logs gives the same lines as parsing them once they're complete.
+    let all_lines = |conn: &rusqlite::Connection| -> Vec<(String, u32, String, String, u64)> {
+        conn.prepare(
+            "
+            SELECT files.relative_path, line_num, lines.timestamp, message, seq FROM lines
+            JOIN files ON files.id = lines.file_id
+            ORDER BY seq",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    };
+    let followed = all_lines(&rusqlite::Connection::open(&db_path).unwrap());
+    assert_eq!(followed.len() as u64, first_count + second_count);
+    test_with_path(
+        &logs_path,
+        &temp_dir.join("parsed"),
+        lumberjack_parse::Options::default(),
+        |conn| assert_eq!(all_lines(&conn), followed),
+    );
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn update_changed_files() {
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    let log = |lines: &[&str]| -> String {
+        let mut log = VERSION_LINE.to_string();
+        for line in lines {
+            log.push_str(line);
+            log.push('\n');
+        }
+        log
+    };
+    let info_log = log(&["2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database"]);
+    let warning_log = log(&["2023-12-08T23:39:24.252743 DB Warning Obj=/DB#102/ Opening database"]);
+
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let logs_path = temp_dir.join("logs");
+    std::fs::create_dir_all(logs_path.join("binary")).unwrap();
+    std::fs::copy(
+        test_data_path("binary_logs").join("cbl_info_1722271848300.cbllog"),
+        logs_path.join("binary/cbl_info_1722271848300.cbllog"),
+    )
+    .unwrap();
+    std::fs::write(logs_path.join("cbl_info_1702078763000.txt"), &info_log).unwrap();
+    std::fs::write(
+        logs_path.join("cbl_warning_1702078764000.txt"),
+        &warning_log,
+    )
+    .unwrap();
+    std::fs::write(
+        logs_path.join("cbl_error_1702078764000.txt"),
+        log(&["2023-12-08T23:39:25.252743 DB Error Obj=/DB#103/ Opening database"]),
+    )
+    .unwrap();
+
+    let db_path = temp_dir.join("updated.sqlite");
+    lumberjack_parse::parse(&logs_path, &db_path, lumberjack_parse::Options::default()).unwrap();
+
+    // The info log is rewritten without changing, the warning log grows, the error log is removed, and the info log is
+    // rotated to a new file.
+    std::fs::write(logs_path.join("cbl_info_1702078763000.txt"), &info_log).unwrap();
+    std::fs::write(
+        logs_path.join("cbl_warning_1702078764000.txt"),
+        warning_log + "2023-12-08T23:39:26.252743 DB Warning Obj=/DB#104/ Opening database\n",
+    )
+    .unwrap();
+    std::fs::remove_file(logs_path.join("cbl_error_1702078764000.txt")).unwrap();
+    std::fs::write(
+        logs_path.join("cbl_info_1702078767000.txt"),
+        log(&["2023-12-08T23:39:27.252743 DB Info Obj=/DB#105/ Opening database"]),
+    )
+    .unwrap();
+
+    let report =
+        lumberjack_parse::update(&logs_path, &db_path, lumberjack_parse::Options::default())
+            .unwrap();
+    let mut unchanged = report.unchanged_files.clone();
+    unchanged.sort();
+    assert_eq!(
+        unchanged,
+        vec![
+            logs_path
+                .join("binary/cbl_info_1722271848300.cbllog")
+                .to_string_lossy()
+                .to_string(),
+            logs_path
+                .join("cbl_info_1702078763000.txt")
+                .to_string_lossy()
+                .to_string(),
+        ]
+    );
+    let mut parsed: Vec<&str> = report
+        .files
+        .iter()
+        .map(|file| file.path.rsplit('/').next().unwrap())
+        .collect();
+    parsed.sort();
+    assert_eq!(
+        parsed,
+        vec![
+            "cbl_info_1702078767000.txt",
+            "cbl_warning_1702078764000.txt"
+        ]
+    );
+
+    // Updating gives the same data as parsing the logs from scratch.
+    let all_files = |conn: &rusqlite::Connection| -> Vec<(String, u32, Option<u32>, String)> {
+        conn.prepare(
+            "
+            SELECT relative_path, first_line_num, line_count, hash FROM files
+            ORDER BY relative_path",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    };
+    let all_lines = |conn: &rusqlite::Connection| -> Vec<(String, u32, String, String, u64)> {
+        conn.prepare(
+            "
+            SELECT files.relative_path, line_num, lines.timestamp, message, seq FROM lines
+            JOIN files ON files.id = lines.file_id
+            ORDER BY seq",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    };
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let (updated_files, updated_lines) = (all_files(&conn), all_lines(&conn));
+    assert_eq!(updated_files.len(), 4);
+    test_with_path(
+        &logs_path,
+        &temp_dir.join("parsed"),
+        lumberjack_parse::Options::default(),
+        |conn| {
+            assert_eq!(all_files(&conn), updated_files);
+            assert_eq!(all_lines(&conn), updated_lines);
+        },
+    );
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn update_adds_event_types() {
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let logs_path = temp_dir.join("logs");
+    std::fs::create_dir_all(&logs_path).unwrap();
+    std::fs::write(
+        logs_path.join("cbl_info_1702078763000.txt"),
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n\
+         2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+    )
+    .unwrap();
+
+    let db_path = temp_dir.join("updated.sqlite");
+    lumberjack_parse::parse(&logs_path, &db_path, lumberjack_parse::Options::default()).unwrap();
+
+    // Simulate a database written by an earlier build with the same schema, before the line's event type was added.
+    let named_lines = |conn: &rusqlite::Connection| -> u32 {
+        conn.query_row(
+            "
+            SELECT COUNT(*) FROM lines
+            JOIN event_types ON event_types.id = lines.event_type",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "
+        PRAGMA foreign_keys=OFF;
+        DELETE FROM event_types WHERE id = (SELECT event_type FROM lines);",
+    )
+    .unwrap();
+    assert_eq!(named_lines(&conn), 0);
+    drop(conn);
+
+    // Updating adds the missing event type, even though no file has changed.
+    let report =
+        lumberjack_parse::update(&logs_path, &db_path, lumberjack_parse::Options::default())
+            .unwrap();
+    assert!(report.files.is_empty());
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    assert_eq!(named_lines(&conn), 1);
+    let event_type_count: u32 = conn
+        .query_row("SELECT COUNT(*) FROM event_types", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(
+        event_type_count as usize,
+        enum_iterator::cardinality::<lumberjack_parse::data::EventType>()
+    );
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn merge_devices() {
+    const VERSION_LINE: &str = "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n";
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+
+    // Each device's logs are parsed into their own database, so file and session IDs start at 0 in both.
+    let parse_device = |name: &str, lines: &[&str]| -> PathBuf {
+        let logs_path = temp_dir.join(name);
+        std::fs::create_dir_all(&logs_path).unwrap();
+        let mut log = VERSION_LINE.to_string();
+        for line in lines {
+            log.push_str(line);
+            log.push('\n');
+        }
+        std::fs::write(logs_path.join("cbl_info_1702078763000.txt"), log).unwrap();
+        let db_path = temp_dir.join(format!("{}.sqlite", name));
+        lumberjack_parse::parse(&logs_path, &db_path, lumberjack_parse::Options::default())
+            .unwrap();
+        db_path
+    };
+    let phone = parse_device(
+        "phone",
+        &[
+            "2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database",
+            "2023-12-08T23:39:25.252743 DB Info Obj=/DB#101/ Closing database",
+        ],
+    );
+    let tablet = parse_device(
+        "tablet",
+        &["2023-12-08T23:39:24.252743 DB Info Obj=/DB#201/ Opening database"],
+    );
+
+    // Simulate a database from another build, whose event type IDs differ and which has an event type this build
+    // doesn't know.
+    let conn = rusqlite::Connection::open(&tablet).unwrap();
+    conn.execute_batch(
+        "
+        PRAGMA foreign_keys=OFF;
+        UPDATE event_types SET id = id + 1000;
+        UPDATE lines SET event_type = event_type + 1000;
+        INSERT INTO event_types (id, name) VALUES (5000, 'FromTheFuture');
+        UPDATE lines SET event_type = 5000 WHERE message LIKE 'Obj=/DB#201/%';",
+    )
+    .unwrap();
+    drop(conn);
+
+    let lines = |conn: &rusqlite::Connection| -> Vec<(String, String, String)> {
+        conn.prepare(
+            "
+            SELECT lines.timestamp, message, event_types.name FROM lines
+            JOIN event_types ON event_types.id = lines.event_type
+            ORDER BY lines.timestamp",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    };
+    let phone_lines = lines(&rusqlite::Connection::open(&phone).unwrap());
+
+    let merged = temp_dir.join("merged.sqlite");
+    lumberjack_parse::merge(
+        &[
+            lumberjack_parse::MergeInput::new(phone.clone()),
+            lumberjack_parse::MergeInput {
+                path: tablet.clone(),
+                device: "galaxy-tab".to_string(),
+            },
+        ],
+        &merged,
+    )
+    .unwrap();
+
+    let conn = rusqlite::Connection::open(&merged).unwrap();
+    let files = conn
+        .prepare("SELECT * FROM files ORDER BY id")
+        .unwrap()
+        .query_map([], lumberjack_parse::data::File::from_row)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        files
+            .iter()
+            .map(|file| (file.id, file.device.as_deref(), file.database.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, Some("phone"), Some(phone.to_string_lossy().to_string())),
+            (
+                1,
+                Some("galaxy-tab"),
+                Some(tablet.to_string_lossy().to_string())
+            ),
+        ]
+    );
+
+    // Every session and line belongs to the file from the same device.
+    let sessions: Vec<(u32, u32)> = conn
+        .prepare("SELECT id, file_id FROM sessions ORDER BY id")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(sessions, vec![(0, 0), (1, 1)]);
+    let mismatched: u32 = conn
+        .query_row(
+            "
+            SELECT COUNT(*) FROM lines
+            JOIN sessions ON sessions.id = lines.session_id
+            WHERE sessions.file_id != lines.file_id",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(mismatched, 0);
+
+    // Event types are matched by name, and lines are sequenced across devices.
+    let merged_lines = lines(&conn);
+    assert_eq!(merged_lines.len(), 3);
+    assert_eq!(merged_lines[0], phone_lines[0]);
+    assert_eq!(merged_lines[1].2, "Unknown");
+    assert_eq!(merged_lines[2], phone_lines[1]);
+    let devices: Vec<(u64, String)> = conn
+        .prepare(
+            "
+            SELECT seq, files.device FROM lines
+            JOIN files ON files.id = lines.file_id
+            ORDER BY seq",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        devices,
+        vec![
+            (1, "phone".to_string()),
+            (2, "galaxy-tab".to_string()),
+            (3, "phone".to_string())
+        ]
+    );
+
+    // A database can't be merged into itself, as the output is replaced.
+    assert!(
+        lumberjack_parse::merge(&[lumberjack_parse::MergeInput::new(phone.clone())], &phone)
+            .is_err()
+    );
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn event_type_ids_of_older_databases() {
+    use lumberjack_parse::data::{EventType, Line};
+
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    std::fs::create_dir_all(&temp_dir).unwrap();
+    let db_path = temp_dir.join("older.sqlite");
+    lumberjack_parse::parse(
+        &test_data_path("binary_logs"),
+        &db_path,
+        lumberjack_parse::Options::default(),
+    )
+    .unwrap();
+
+    let read_lines = |conn: &rusqlite::Connection| -> Vec<(u32, u32, EventType)> {
+        conn.prepare("SELECT * FROM lines ORDER BY file_id, line_num")
+            .unwrap()
+            .query_map([], Line::from_row)
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|line| (line.file_id, line.line_num, line.event_type))
+            .collect()
+    };
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let expected = read_lines(&conn);
+    assert!(expected
+        .iter()
+        .any(|(_, _, event_type)| *event_type != EventType::Unknown));
+    let version: u32 = conn
+        .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(version, lumberjack_parse::data::SCHEMA_VERSION);
+
+    // Earlier builds numbered event types by their position among every event, so a database from a build with other
+    // events has different IDs, and the schema version before they were permanent.
+    conn.execute_batch(
+        "
+        PRAGMA foreign_keys=OFF;
+        UPDATE schema_version SET version = 2;
+        UPDATE event_types SET id = 1000 - id;
+        UPDATE lines SET event_type = 1000 - event_type;
+        INSERT INTO event_types (id, name) VALUES (2000, 'RemovedEvent');",
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE lines SET event_type = 2000 WHERE file_id = $1 AND line_num = $2",
+        [expected[0].0, expected[0].1],
+    )
+    .unwrap();
+    drop(conn);
+
+    // Opening the database translates the IDs by name, so every line has the same event type as before.
+    let conn = lumberjack_parse::data::open_db(&db_path, false).unwrap();
+    let mut upgraded_expected = expected.clone();
+    upgraded_expected[0].2 = EventType::Unknown;
+    assert_eq!(read_lines(&conn), upgraded_expected);
+    let names_match: bool = conn
+        .query_row(
+            "SELECT COUNT(*) = $1 FROM event_types",
+            [enum_iterator::cardinality::<EventType>() as u32],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(names_match);
+
+    // It's only translated once.
+    drop(conn);
+    let conn = lumberjack_parse::data::open_db(&db_path, false).unwrap();
+    assert_eq!(read_lines(&conn), upgraded_expected);
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
+
+#[test]
+fn upgrade_unversioned_database() {
+    use lumberjack_parse::data::{EventType, File, Line, SCHEMA_VERSION};
+
+    let temp_dir = std::env::temp_dir()
+        .join("lumberjack_test_queries/")
+        .join(format!("{}/", epoch_id()));
+    let logs_path = temp_dir.join("logs");
+    std::fs::create_dir_all(&logs_path).unwrap();
+    let log_path = logs_path.join("cbl_info_1702078763000.txt");
+    std::fs::write(
+        &log_path,
+        "---- CouchbaseLite/3.2.0 (.NET; Microsoft Windows 10.0.22621) Build/1 LiteCore/3.2.0 (1) Commit/86734653b94fa6db+7f0707145d9db2af ----\n\
+         2023-12-08T23:39:23.252743 DB Info Obj=/DB#101/ Opening database\n",
+    )
+    .unwrap();
+
+    // A database written before the schema was versioned, with the event type IDs of an earlier build.
+    let db_path = temp_dir.join("unversioned.sqlite");
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    conn.execute_batch(include_str!("../src/data/migrations/0001_initial.sql"))
+        .unwrap();
+    for event_type in enum_iterator::all::<EventType>() {
+        conn.execute(
+            "INSERT INTO event_types (id, name) VALUES ($1, $2)",
+            rusqlite::params![1000 - event_type as u32, event_type.to_string()],
+        )
+        .unwrap();
+    }
+    let event_type = enum_iterator::first::<EventType>().unwrap();
+    conn.execute(
+        "INSERT INTO files (id, path, timestamp) VALUES (0, $1, '2023-12-08T23:39:23')",
+        [log_path.to_string_lossy()],
+    )
+    .unwrap();
+    conn.execute(
+        "
+        INSERT INTO lines (file_id, line_num, level, timestamp, domain, event_type, event_data, object_path)
+        VALUES
+            (0, 2, 2, '2023-12-08T23:39:24.252743', 'DB', $1, NULL, NULL),
+            (0, 1, 2, '2023-12-08T23:39:23.252743', 'DB', $1, NULL, '/DB#101/')",
+        [1000 - event_type as u32],
+    )
+    .unwrap();
+    drop(conn);
+
+    // Merging it upgrades a copy, leaving the input unchanged.
+    let unversioned = std::fs::read(&db_path).unwrap();
+    let merged_path = temp_dir.join("merged.sqlite");
+    lumberjack_parse::merge(
+        &[lumberjack_parse::MergeInput::new(db_path.clone())],
+        &merged_path,
+    )
+    .unwrap();
+    assert_eq!(std::fs::read(&db_path).unwrap(), unversioned);
+    let merged_lines: Vec<(u32, EventType)> = rusqlite::Connection::open(&merged_path)
+        .unwrap()
+        .prepare("SELECT * FROM lines ORDER BY seq")
+        .unwrap()
+        .query_map([], Line::from_row)
+        .unwrap()
+        .map(Result::unwrap)
+        .map(|line| (line.line_num, line.event_type))
+        .collect();
+    assert_eq!(merged_lines, vec![(1, event_type), (2, event_type)]);
+
+    // Opening it upgrades it in place, so it can be read like a new database.
+    let conn = lumberjack_parse::data::open_db(&db_path, false).unwrap();
+    let version: u32 = conn
+        .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+    let file = conn
+        .query_row("SELECT * FROM files", [], File::from_row)
+        .unwrap();
+    assert_eq!(file.relative_path, file.path);
+    assert_eq!(file.hash, "");
+    let lines = conn
+        .prepare("SELECT * FROM lines ORDER BY seq")
+        .unwrap()
+        .query_map([], Line::from_row)
+        .unwrap()
+        .map(Result::unwrap)
+        .map(|line| (line.line_num, line.event_type, line.seq))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![(1, event_type, Some(1)), (2, event_type, Some(2))]
+    );
+    drop(conn);
+
+    // The upgraded database can be updated, which parses the file again as its hash wasn't known.
+    let report =
+        lumberjack_parse::update(&logs_path, &db_path, lumberjack_parse::Options::default())
+            .unwrap();
+    assert_eq!(report.files.len(), 1);
+    assert!(report.unchanged_files.is_empty());
+
+    // A database from a later version can't be read.
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    conn.execute(
+        "UPDATE schema_version SET version = $1",
+        [SCHEMA_VERSION + 1],
+    )
+    .unwrap();
+    drop(conn);
+    assert!(matches!(
+        lumberjack_parse::data::open_db(&db_path, false),
+        Err(lumberjack_parse::Error::SchemaTooNew(version, SCHEMA_VERSION)) if version == SCHEMA_VERSION + 1
+    ));
+
+    if std::env::var("LUMBERJACK_TEST_KEEP").is_err() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
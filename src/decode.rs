@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use lumberjack_parse::decoder::{self, Decoder, Entry};
+
+use crate::{DecodeArgs, Error, Result};
+
+/// Decode the binary logs given by `args` to text, in the same format as CBL's text logs.
+pub fn decode(args: &DecodeArgs) -> Result<()> {
+    let paths = find_binary_logs(&args.inputs)?;
+    if paths.is_empty() {
+        log::warn!("No binary logs found in {:?}", args.inputs);
+        return Ok(());
+    }
+
+    if args.merge {
+        let decoders = paths
+            .iter()
+            .map(Decoder::open)
+            .collect::<lumberjack_parse::Result<Vec<_>>>()?;
+        let mut writer = create_writer(args.output.as_deref())?;
+        write_entries(decoder::merge(decoders), &mut writer, args.recover)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let out_paths = match &args.output {
+        Some(out_dir) => {
+            std::fs::create_dir_all(out_dir)?;
+            output_paths(&paths, out_dir)?
+        }
+        None => vec![None; paths.len()],
+    };
+    for (path, out_path) in paths.iter().zip(out_paths) {
+        let mut writer = create_writer(out_path.as_deref())?;
+        log::debug!("Decoding {:?}", path);
+        write_entries(Decoder::open(path)?, &mut writer, args.recover)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// The binary log files in `inputs`, which may be files or directories, in order of path within each directory.
+fn find_binary_logs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for input in inputs {
+        if input.is_dir() {
+            let mut files = vec![];
+            for entry in std::fs::read_dir(input)? {
+                let path = entry?.path();
+                if path.is_file() && decoder::is_encoded(&path)? {
+                    files.push(path);
+                }
+            }
+            files.sort();
+            paths.extend(files);
+        } else {
+            paths.push(input.clone());
+        }
+    }
+    Ok(paths)
+}
+
+/// The text log to write for each binary log in `out_dir`, named after the binary log. Fails before anything is written
+/// if two binary logs with the same name would overwrite each other's output.
+fn output_paths(paths: &[PathBuf], out_dir: &Path) -> Result<Vec<Option<PathBuf>>> {
+    let mut inputs: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    let mut out_paths = Vec::with_capacity(paths.len());
+    for path in paths {
+        let out_path = out_dir.join(Path::new(path.file_name().unwrap()).with_extension("txt"));
+        if let Some(other) = inputs.insert(out_path.clone(), path) {
+            return Err(Error::OutputCollision(
+                other.clone(),
+                path.clone(),
+                out_path,
+            ));
+        }
+        out_paths.push(Some(out_path));
+    }
+    Ok(out_paths)
+}
+
+/// A writer to the file at `path`, or to stdout if there is no path.
+fn create_writer(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => {
+            log::info!("Writing decoded logs to {:?}", path);
+            Box::new(BufWriter::new(File::create(path)?))
+        }
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    })
+}
+
+/// Write each entry as a line of text. Corrupt regions are skipped with a warning if `recover` is set, otherwise they
+/// stop decoding with an error.
+fn write_entries(
+    entries: impl Iterator<Item = lumberjack_parse::Result<Entry>>,
+    writer: &mut impl Write,
+    recover: bool,
+) -> Result<()> {
+    for entry in entries {
+        match entry {
+            Ok(entry) => writeln!(writer, "{}", entry)?,
+            Err(err @ lumberjack_parse::Error::CorruptBinaryLogs(..)) if recover => {
+                log::warn!("{}", err);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,30 @@
+mod decode;
+mod report;
 #[cfg(feature = "xlsx")]
 mod xlsx;
 
-use clap::Parser;
+use clap::{Args as ClapArgs, CommandFactory, Parser, Subcommand, ValueEnum};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
 
+/// The input path which reads logs from stdin.
+const STDIN_PATH: &str = "-";
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    #[arg(short, long)]
-    /// The input path of log file(s) to parse
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(short, long, required = true)]
+    /// The input path of log file(s) to parse. A log file, a directory, or a .zip, .tar or .tar.gz archive of logs.
+    /// `-` reads text or binary logs from stdin. Text logs from stdin whose version line isn't within their first 10,000
+    /// lines (i.e. from `adb logcat`, started after the app) need `--cbl-version` and `--platform`
+    input: Option<PathBuf>,
+    #[arg(long, default_value = "stdin")]
+    /// The name of the logs read from stdin with `-i -`, stored as their path. If it's named like a CBL log file
+    /// (i.e. cbl_info_1722271812929.cbllog) the timestamp and level are taken from it
+    label: String,
     #[cfg(feature = "xlsx")]
     #[arg(long, default_value_t = false)]
     /// If specified, output the parsed data to an xlsx file
@@ -20,16 +34,103 @@ struct Args {
     /// A directory or a file name. If a directory is specified, the file name will be chosen by the program.
     /// If no output parameter is specified, the files will be output to the current directory.
     output: Option<PathBuf>,
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     /// Enable verbose logging
     verbose: bool,
-    #[arg(long)]
+    #[arg(long, global = true)]
     /// Enable trace logging
     trace: bool,
     #[arg(long)]
     /// Reduce and coalesce similar log lines in trace output. Useful when dealing with a large number of parsing errors.
     /// Ignored in release builds.
     reduce_lines: bool,
+    #[arg(long)]
+    /// Don't store CBL lines which don't match any known event.
+    /// By default they are stored with the 'Unknown' event type.
+    skip_unknown: bool,
+    #[arg(long)]
+    /// Skip corrupt regions of binary logs, such as those truncated by a crash, and continue from the next valid entry.
+    /// By default parsing of a corrupt file stops at the corruption.
+    recover: bool,
+    #[arg(long, requires = "platform")]
+    /// Parse every file with the patterns for this CBL version (i.e. 3.2.0), instead of the version detected from each
+    /// file. Useful for logs with no version line. Must be given with `--platform`.
+    cbl_version: Option<semver::Version>,
+    #[arg(long, requires = "cbl_version")]
+    /// The name of the platform patterns to use with `--cbl-version` (see `parse/src/patterns`), i.e. "vanilla"
+    platform: Option<String>,
+    #[arg(long, value_name = "GLOB")]
+    /// Only parse files whose path relative to the input directory matches this glob, i.e. "**/cbl_info_*".
+    /// May be given multiple times. Files within archives are matched as "<archive>!/<path in archive>"
+    include: Vec<String>,
+    #[arg(long, value_name = "GLOB")]
+    /// Don't parse files, or search directories, whose path relative to the input directory matches this glob.
+    /// May be given multiple times
+    exclude: Vec<String>,
+    #[arg(long)]
+    /// How many levels of subdirectories of the input directory to search for logs. By default all are searched
+    max_depth: Option<usize>,
+    #[arg(long, conflicts_with = "follow")]
+    /// Update the output database from a previous run instead of replacing it. Only files which have changed since are
+    /// parsed again, and new files are added
+    update: bool,
+    #[arg(long)]
+    /// Keep parsing lines as they're written to the input logs, including new files created by rotation, until
+    /// stopped. Lines are added to the database as they're parsed, so it can be queried while following
+    follow: bool,
+    #[arg(long, value_name = "MS", default_value_t = 1000, requires = "follow")]
+    /// How often to check for new lines with `--follow`, in milliseconds
+    poll_interval: u64,
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    /// How to print the summary of the parsed files to stdout
+    report: ReportFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Decode binary logs to the CBL text log format
+    Decode(DecodeArgs),
+    /// Merge databases from separate runs (i.e. one per device) into one database, so they can be queried together
+    Merge(MergeArgs),
+}
+
+#[derive(ClapArgs, Debug)]
+struct DecodeArgs {
+    #[arg(required = true)]
+    /// Binary log files, or directories containing binary logs
+    inputs: Vec<PathBuf>,
+    #[arg(short, long)]
+    /// The directory to write a text log for each binary log to, or with `--merge`, the file to write the merged logs
+    /// to. If no output parameter is specified, the logs are written to stdout.
+    output: Option<PathBuf>,
+    #[arg(short, long)]
+    /// Merge the logs (i.e. `cbl_info_*`, `cbl_debug_*`) into a single log ordered by timestamp
+    merge: bool,
+    #[arg(long)]
+    /// Skip corrupt regions of binary logs and continue from the next valid entry.
+    /// By default decoding of a corrupt file stops at the corruption.
+    recover: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct MergeArgs {
+    #[arg(required = true, value_name = "[DEVICE=]DATABASE", value_parser = parse_merge_input)]
+    /// Databases to merge. Each file is tagged with the device its logs are from, which is named after the database
+    /// file (i.e. "pixel-7" for pixel-7.sqlite) unless given as `DEVICE=DATABASE`
+    inputs: Vec<lumberjack_parse::MergeInput>,
+    #[arg(short, long, default_value = "merged.sqlite")]
+    /// The path of the merged database, which is replaced if it exists
+    output: PathBuf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    /// A table with one row per file
+    Table,
+    /// The full report as JSON
+    Json,
+    /// Don't print the report
+    None,
 }
 
 #[derive(Error, Debug)]
@@ -38,9 +139,15 @@ enum Error {
     SQLite(#[from] rusqlite::Error),
     #[error("Parse Error {0}")]
     Parse(#[from] lumberjack_parse::Error),
+    #[error("IO Error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON Error {0}")]
+    Json(#[from] serde_json::Error),
     #[cfg(feature = "xlsx")]
     #[error("Xlsx Error {0}")]
     Xlsx(#[from] rust_xlsxwriter::XlsxError),
+    #[error("Decoded logs of {0:?} and {1:?} would both be written to {2:?}")]
+    OutputCollision(PathBuf, PathBuf, PathBuf),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -61,6 +168,17 @@ fn main() -> Result<()> {
         .filter_level(level_filter)
         .init();
 
+    if let Some(Command::Decode(decode_args)) = &args.command {
+        return decode::decode(decode_args);
+    }
+
+    if let Some(Command::Merge(merge_args)) = &args.command {
+        return Ok(lumberjack_parse::merge(
+            &merge_args.inputs,
+            &merge_args.output,
+        )?);
+    }
+
     let Options {
         in_dir,
         out_dir,
@@ -71,9 +189,51 @@ fn main() -> Result<()> {
 
     let parser_options = lumberjack_parse::Options {
         reduce_lines: args.reduce_lines,
+        skip_unknown: args.skip_unknown,
+        recover: args.recover,
+        cbl_version: args.cbl_version.clone(),
+        platform: args.platform.clone(),
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+        max_depth: args.max_depth,
     };
 
-    lumberjack_parse::parse(&in_dir, &db_path, parser_options)?;
+    if in_dir == Path::new(STDIN_PATH) && (args.follow || args.update) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--follow and --update can't be used to read logs from stdin",
+            )
+            .exit();
+    }
+
+    if args.follow {
+        return follow(
+            &in_dir,
+            &db_path,
+            parser_options,
+            Duration::from_millis(args.poll_interval),
+        );
+    }
+
+    let report = if in_dir == Path::new(STDIN_PATH) {
+        lumberjack_parse::parse_reader(
+            std::io::BufReader::new(std::io::stdin()),
+            &args.label,
+            &db_path,
+            parser_options,
+        )?
+    } else if args.update {
+        lumberjack_parse::update(&in_dir, &db_path, parser_options)?
+    } else {
+        lumberjack_parse::parse(&in_dir, &db_path, parser_options)?
+    };
+
+    match args.report {
+        ReportFormat::Table => report::print_table(&report),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        ReportFormat::None => (),
+    }
 
     #[cfg(feature = "xlsx")]
     if args.xlsx {
@@ -86,6 +246,45 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Parse the logs at `in_dir` as they're written, checking for new lines every `interval`, until the process is
+/// stopped.
+fn follow(
+    in_dir: &Path,
+    db_path: &Path,
+    options: lumberjack_parse::Options,
+    interval: Duration,
+) -> Result<()> {
+    let mut follower = lumberjack_parse::Follower::new(in_dir, db_path, options)?;
+    log::info!(
+        "Following logs at {:?}, writing to {:?}. Press Ctrl+C to stop.",
+        in_dir,
+        db_path
+    );
+    loop {
+        let (_, line_count) = follower.poll()?;
+        if line_count > 0 {
+            log::info!("Added {} lines", line_count);
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Parse a `[DEVICE=]DATABASE` argument of `merge`. A path which contains '=' is only split if it isn't a file.
+fn parse_merge_input(arg: &str) -> std::result::Result<lumberjack_parse::MergeInput, String> {
+    match arg.split_once('=') {
+        Some((device, path)) if !Path::new(arg).is_file() => {
+            if device.is_empty() {
+                return Err("the device name is empty".to_string());
+            }
+            Ok(lumberjack_parse::MergeInput {
+                path: path.into(),
+                device: device.to_string(),
+            })
+        }
+        _ => Ok(lumberjack_parse::MergeInput::new(arg.into())),
+    }
+}
+
 struct Options {
     in_dir: PathBuf,
     out_dir: PathBuf,
@@ -120,10 +319,14 @@ fn resolve_args(args: &Args) -> Options {
         out_dir
     };
 
-    let in_dir = if args.input.is_relative() {
-        current_dir.join(&args.input)
+    let input = args
+        .input
+        .clone()
+        .expect("input is required without a subcommand");
+    let in_dir = if input.is_relative() && input != Path::new(STDIN_PATH) {
+        current_dir.join(input)
     } else {
-        args.input.clone()
+        input
     };
 
     if !out_dir.exists() {
@@ -0,0 +1,120 @@
+use lumberjack_parse::ParseReport;
+
+const HEADERS: [&str; 9] = [
+    "File", "Version", "Platform", "Lines", "Parsed", "Unknown", "Errors", "Ignored", "Time",
+];
+
+/// Print a summary table of the report to stdout, with one row per file and a total row.
+pub fn print_table(report: &ParseReport) {
+    let mut rows: Vec<[String; 9]> = report
+        .files
+        .iter()
+        .map(|file| {
+            let name = std::path::Path::new(&file.path)
+                .file_name()
+                .map_or(file.path.clone(), |name| name.to_string_lossy().to_string());
+            [
+                name,
+                file.version.clone(),
+                file.platform.clone(),
+                file.line_count.to_string(),
+                file.parsed_count.to_string(),
+                file.unknown_count.to_string(),
+                file.error_count.to_string(),
+                file.ignored_count.to_string(),
+                format!("{:.2}s", file.duration.as_secs_f64()),
+            ]
+        })
+        .collect();
+
+    rows.push([
+        "Total".to_string(),
+        String::new(),
+        String::new(),
+        report.line_count().to_string(),
+        report.parsed_count().to_string(),
+        report.unknown_count().to_string(),
+        report.error_count().to_string(),
+        report.ignored_count().to_string(),
+        format!("{:.2}s", report.duration.as_secs_f64()),
+    ]);
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    print_row(&HEADERS.map(str::to_string), &widths);
+    let separator = widths.map(|width| "-".repeat(width));
+    print_row(&separator, &widths);
+    let (total, rows) = rows.split_last().unwrap();
+    for row in rows {
+        print_row(row, &widths);
+    }
+    print_row(&separator, &widths);
+    print_row(total, &widths);
+
+    let errors = report.errors();
+    if !errors.is_empty() {
+        println!();
+        println!("Failed and ignored lines by kind:");
+        for (kind, count) in errors {
+            println!("  {kind}: {count}");
+        }
+    }
+
+    let skipped: Vec<_> = report
+        .files
+        .iter()
+        .flat_map(|file| {
+            file.skipped_regions
+                .iter()
+                .map(move |region| (file, region))
+        })
+        .collect();
+    if !skipped.is_empty() {
+        println!();
+        println!("Skipped corrupt regions:");
+        for (file, region) in skipped {
+            println!(
+                "  {}: {} bytes at {} ({})",
+                file.path, region.length, region.offset, region.error
+            );
+        }
+    }
+
+    if !report.failed_files.is_empty() {
+        println!();
+        println!("Failed files:");
+        for failed in &report.failed_files {
+            println!("  {}: {}", failed.path, failed.error);
+        }
+    }
+
+    if !report.unchanged_files.is_empty() {
+        println!();
+        println!(
+            "Skipped {} files which haven't changed since the last update",
+            report.unchanged_files.len()
+        );
+    }
+}
+
+fn print_row(row: &[String; 9], widths: &[usize; 9]) {
+    let cells: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(i, (cell, width))| {
+            // Left-align the text columns, right-align the numbers.
+            if i < 3 {
+                format!("{cell:<width$}")
+            } else {
+                format!("{cell:>width$}")
+            }
+        })
+        .collect();
+    println!("{}", cells.join("  ").trim_end());
+}
@@ -41,6 +41,33 @@ pub fn write(path: impl AsRef<Path>, db: rusqlite::Connection) -> crate::Result<
 
     writer.write_worksheet_serializable("Files", &files)?;
 
+    let sessions: Vec<lumberjack_parse::data::Session> = db
+        .prepare("SELECT * FROM sessions")
+        .unwrap()
+        .query_map([], lumberjack_parse::data::Session::from_row)?
+        .filter_map(Result::ok)
+        .collect();
+
+    writer.write_worksheet_serializable("Sessions", &sessions)?;
+
+    let metadata: Vec<lumberjack_parse::data::Metadata> = db
+        .prepare("SELECT * FROM metadata")
+        .unwrap()
+        .query_map([], lumberjack_parse::data::Metadata::from_row)?
+        .filter_map(Result::ok)
+        .collect();
+
+    writer.write_worksheet_serializable("Metadata", &metadata)?;
+
+    let parse_errors: Vec<lumberjack_parse::data::ParseError> = db
+        .prepare("SELECT * FROM parse_errors")
+        .unwrap()
+        .query_map([], lumberjack_parse::data::ParseError::from_row)?
+        .filter_map(Result::ok)
+        .collect();
+
+    writer.write_worksheet_serializable("Parse Errors", &parse_errors)?;
+
     let path_str = path.as_ref().to_string_lossy();
     writer.save(&path_str)?;
     log::info!("Saved XLSX file to \"{}\"", &path_str);
@@ -12,11 +12,38 @@ where
     serializer.serialize_str(&t.to_string())
 }
 
+fn serialize_option_to_string<S, T>(t: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: ToString,
+{
+    match t {
+        Some(t) => serializer.serialize_str(&t.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
 #[derive(Serialize)]
 pub struct File {
     id: u32,
     path: String,
+    relative_path: String,
+    archive_path: Option<String>,
     timestamp: NaiveDateTime,
+    version: String,
+    platform: String,
+    #[serde(serialize_with = "serialize_option_to_string")]
+    level: Option<lumberjack_parse::data::Level>,
+    predecessor_id: Option<u32>,
+    first_line_num: u32,
+    #[serde(serialize_with = "serialize_to_string")]
+    patterns_source: lumberjack_parse::data::PatternsSource,
+    size: u64,
+    modified: Option<NaiveDateTime>,
+    hash: String,
+    line_count: Option<u32>,
+    database: Option<String>,
+    device: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -31,6 +58,10 @@ pub struct Line {
     event_type: lumberjack_parse::data::EventType,
     event_data: Option<String>,
     object_path: Option<String>,
+    session_id: Option<u32>,
+    message: String,
+    offset: u64,
+    seq: Option<u64>,
 }
 
 impl From<lumberjack_parse::data::File> for File {
@@ -38,7 +69,21 @@ impl From<lumberjack_parse::data::File> for File {
         File {
             id: file.id,
             path: file.path,
+            relative_path: file.relative_path,
+            archive_path: file.archive_path,
             timestamp: file.timestamp,
+            version: file.version,
+            platform: file.platform,
+            level: file.level,
+            predecessor_id: file.predecessor_id,
+            first_line_num: file.first_line_num,
+            patterns_source: file.patterns_source,
+            size: file.size,
+            modified: file.modified,
+            hash: file.hash,
+            line_count: file.line_count,
+            database: file.database,
+            device: file.device,
         }
     }
 }
@@ -54,6 +99,10 @@ impl From<lumberjack_parse::data::Line> for Line {
             event_type: value.event_type,
             event_data: value.event_data,
             object_path: value.object_path,
+            session_id: value.session_id,
+            message: value.message,
+            offset: value.offset,
+            seq: value.seq,
         }
     }
 }